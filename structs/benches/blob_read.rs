@@ -0,0 +1,64 @@
+//! Compares ReadOnlyBlobStore::get, which reads the file,
+//! with MappedBlobStore::get, which slices a memory map of it.
+//! Run with `cargo bench --bench blob_read --features mmap`, optionally passing
+//! a key count after `--` (defaults to 100 thousand).
+
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use rust_data_structures_algorithms::{BlobStore, MappedBlobStore, ReadOnlyBlobStore};
+
+fn time<F: FnOnce()>(f: F) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+fn main() {
+    let n: u64 = std::env::args()
+        .skip(1)
+        .find_map(|a| a.parse().ok())
+        .unwrap_or(100_000);
+    let fname = std::env::temp_dir().join("blob_read_bench");
+    let fname = fname.to_str().expect("temp dir is unicode");
+    std::fs::remove_file(fname).ok();
+
+    //about 40 entries a block, well short of full
+    let mut bs = BlobStore::new(fname, 4096, n / 20 + 1).unwrap();
+    for k in 0..n {
+        bs.insert(k, format!("value number {}", k)).unwrap();
+    }
+    let mut keys: Vec<u64> = (0..n).collect();
+    keys.shuffle(&mut StdRng::seed_from_u64(1));
+    //the writer has to close before readers can share the file
+    drop(bs);
+    let bs = ReadOnlyBlobStore::open(fname).unwrap();
+    let ms = MappedBlobStore::open(fname).unwrap();
+
+    let mut total = 0;
+    let file = time(|| {
+        for k in &keys {
+            total += bs.get(k).unwrap().get_v::<String>().unwrap().len();
+        }
+    });
+    let mapped = time(|| {
+        for k in &keys {
+            total += ms.get(k).unwrap().get_v::<&str>().unwrap().len();
+        }
+    });
+    println!("{} gets of {} keys, {} value bytes", n, n, total / 2);
+    println!(
+        "  {:<10} {:>10.1?}  {:>8.0?}/get",
+        "file",
+        file,
+        file / n as u32
+    );
+    println!(
+        "  {:<10} {:>10.1?}  {:>8.0?}/get",
+        "mapped",
+        mapped,
+        mapped / n as u32
+    );
+    drop((bs, ms));
+    std::fs::remove_file(fname).ok();
+}
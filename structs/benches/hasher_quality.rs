@@ -0,0 +1,11 @@
+//! Prints the quality report for every hasher the crate ships.
+//!
+//! cargo bench --bench hasher_quality
+
+use rust_data_structures_algorithms::quality;
+
+fn main() {
+    for r in quality::shipped() {
+        println!("{}", r);
+    }
+}
@@ -0,0 +1,71 @@
+//! Compares the chained and open addressing HMap backends.
+//! Run with `cargo bench --bench hmap`, optionally passing a key count
+//! after `--` (defaults to 10 million).
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_data_structures_algorithms::{BucketList, HMap, OpenTable, RandomMHash, Table};
+
+fn time<F: FnOnce()>(f: F) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+fn run<S, T>(name: &str, hasher: S, keys: &[u64], misses: &[u64])
+where
+    S: BuildHasher,
+    T: Table<u64, u64>,
+{
+    let mut hm: HMap<u64, u64, S, T> = HMap::with_hasher(hasher);
+    let insert = time(|| {
+        for &k in keys {
+            hm.insert(k, k);
+        }
+    });
+    let mut found = 0;
+    let hit = time(|| {
+        for k in keys {
+            found += hm.get(k).is_some() as usize;
+        }
+    });
+    let miss = time(|| {
+        for k in misses {
+            found += hm.get(k).is_some() as usize;
+        }
+    });
+    assert_eq!(found, keys.len());
+    let remove = time(|| {
+        for k in keys {
+            hm.remove(k);
+        }
+    });
+    println!(
+        "{:<8} insert {:>9.1?}  get hit {:>9.1?}  get miss {:>9.1?}  remove {:>9.1?}",
+        name, insert, hit, miss, remove
+    );
+}
+
+fn main() {
+    let n: usize = std::env::args()
+        .skip(1)
+        .find_map(|a| a.parse().ok())
+        .unwrap_or(10_000_000);
+    //Odd keys go in, even keys are looked up to miss
+    let mut rng = StdRng::seed_from_u64(7);
+    let keys: Vec<u64> = (0..n).map(|_| rng.gen::<u64>() | 1).collect();
+    let misses: Vec<u64> = (0..n).map(|_| rng.gen::<u64>() & !1).collect();
+
+    println!("{} u64 keys, MHash", n);
+    run::<_, BucketList<u64, u64>>("chained", RandomMHash::with_seed(1), &keys, &misses);
+    run::<_, OpenTable<u64, u64>>("open", RandomMHash::with_seed(1), &keys, &misses);
+    //MHash costs more than either table, so std's SipHash shows the tables more clearly
+    println!("{} u64 keys, SipHash", n);
+    run::<_, BucketList<u64, u64>>("chained", RandomState::new(), &keys, &misses);
+    run::<_, OpenTable<u64, u64>>("open", RandomState::new(), &keys, &misses);
+}
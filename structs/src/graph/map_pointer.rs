@@ -1,13 +1,10 @@
-use std::{
-    collections::{HashMap, HashSet},
-    fmt,
-    hash::Hash,
-    rc::Rc,
-};
+use std::{collections::HashMap, fmt, hash::Hash, rc::Rc};
 
 use rand::prelude::SliceRandom;
 use thiserror::Error;
 
+use crate::HSet;
+
 #[derive(Error, Debug)]
 pub enum GraphErr {
     #[error("Node not found '{0}'")]
@@ -113,13 +110,13 @@ where
     }
 
     pub fn shortest_path_r(&self, from: Rc<Route<ID>>, to: ID) -> Option<Rc<Route<ID>>> {
-        let mut toset = HashSet::new();
+        let mut toset = HSet::new();
         toset.insert(to);
         self.closest(from, &toset)
     }
 
-    pub fn closest(&self, from: Rc<Route<ID>>, to: &HashSet<ID>) -> Option<Rc<Route<ID>>> {
-        let mut visited = HashSet::new();
+    pub fn closest(&self, from: Rc<Route<ID>>, to: &HSet<ID>) -> Option<Rc<Route<ID>>> {
+        let mut visited = HSet::new();
         let mut routes = Vec::new();
         routes.push(from);
         loop {
@@ -170,7 +167,7 @@ where
     }
 
     pub fn greedy_salesman(&self, start: ID) -> Option<Rc<Route<ID>>> {
-        let mut to_visit: HashSet<ID> = self.data.keys().cloned().collect();
+        let mut to_visit: HSet<ID> = self.data.keys().cloned().collect();
         to_visit.remove(&start);
         let mut route = Route::start(start.clone());
         while !to_visit.is_empty() {
@@ -237,7 +234,7 @@ where
 #[test]
 fn experiment() -> Result<(), GraphErr> {
     let mut g = Graph::new();
-    for x in vec!['A', 'B', 'C', 'D', 'E', 'F', 'G', 'H'] {
+    for x in ['A', 'B', 'C', 'D', 'E', 'F', 'G', 'H'] {
         g.add_node(x, ());
     }
     g.add_edge('a', 'H', 'D', 6)?;
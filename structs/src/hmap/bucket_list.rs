@@ -1,64 +1,134 @@
-use std::{borrow::Borrow, hash::Hash};
+use std::{
+    borrow::Borrow,
+    hash::Hash,
+    iter::{FlatMap, Flatten},
+    slice, vec,
+};
 
-use super::hasher::hash;
+use super::table::{add_count, Pos, Table};
 
-pub(super) const BSIZE: usize = 8;
+///Default length a bucket may reach before the map grows
+pub(super) const MAX_BUCKET: usize = 4;
 
-#[derive(Debug)]
+type DrainFn<'a, K, V> = fn(&'a mut Vec<(K, V)>) -> vec::Drain<'a, (K, V)>;
+
+///Chained buckets.  Buckets are chosen from a hash worked out by the owning HMap,
+///so main and grow can share one hasher and one hash per lookup.
+#[derive(Debug, Clone)]
 pub struct BucketList<K, V> {
-    seed: u64,
     len: usize,
     pub(super) buckets: Vec<Vec<(K, V)>>,
 }
 
-impl<K, V> BucketList<K, V>
-where
-    K: Hash + Eq,
-{
-    pub(super) fn new() -> Self {
+impl<K, V> BucketList<K, V> {
+    fn bucket_of(&self, h: u64) -> usize {
+        (h % self.buckets.len() as u64) as usize
+    }
+}
+
+impl<K, V> Table<K, V> for BucketList<K, V> {
+    type Iter<'a>
+        = Flatten<slice::Iter<'a, Vec<(K, V)>>>
+    where
+        K: 'a,
+        V: 'a;
+    type IterMut<'a>
+        = Flatten<slice::IterMut<'a, Vec<(K, V)>>>
+    where
+        K: 'a,
+        V: 'a;
+    type IntoIter = Flatten<vec::IntoIter<Vec<(K, V)>>>;
+    type Drain<'a>
+        = FlatMap<slice::IterMut<'a, Vec<(K, V)>>, vec::Drain<'a, (K, V)>, DrainFn<'a, K, V>>
+    where
+        K: 'a,
+        V: 'a;
+
+    fn new() -> Self {
         BucketList {
-            seed: rand::random(),
             len: 0,
             buckets: vec![Vec::new()],
         }
     }
 
-    pub(super) fn push(&mut self, k: K, v: V) -> usize {
-        let h = (hash(self.seed, &k) as usize) % self.buckets.len();
-        self.buckets[h].push((k, v));
-        self.len += 1;
-        self.buckets[h].len()
+    ///Growth settles at around max_bucket / 4 entries per bucket,
+    ///so capacity is worked out from that.
+    fn buckets_for(n: usize, max_bucket: usize) -> usize {
+        (n * 4).div_ceil(max_bucket).max(1)
     }
 
-    pub(super) fn get<KB>(&self, k: &KB) -> Option<&V>
-    where
-        K: Borrow<KB>,
-        KB: Hash + Eq + ?Sized,
-    {
-        let h = (hash(self.seed, &k) as usize) % self.buckets.len();
-        for (ik, iv) in &self.buckets[h] {
-            if k == ik.borrow() {
-                return Some(iv);
-            }
+    fn capacity_of(nb: usize, max_bucket: usize) -> usize {
+        nb * max_bucket / 4
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn b_len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    fn set_buckets(&mut self, n: usize) {
+        debug_assert_eq!(self.len, 0);
+        self.buckets.truncate(n.max(1));
+        self.buckets.shrink_to_fit();
+        for _ in self.buckets.len()..n {
+            self.buckets.push(Vec::new())
         }
-        None
     }
 
-    pub(super) fn get_mut<KB>(&mut self, k: &KB) -> Option<&mut V>
+    fn find<KB>(&self, h: u64, k: &KB) -> Option<Pos>
     where
         K: Borrow<KB>,
         KB: Hash + Eq + ?Sized,
     {
-        let h = (hash(self.seed, &k) as usize) % self.buckets.len();
-        for (ik, iv) in &mut self.buckets[h] {
-            if k == (ik as &K).borrow() {
-                return Some(iv);
-            }
+        let b = self.bucket_of(h);
+        let i = self.buckets[b]
+            .iter()
+            .position(|(ik, _)| k == ik.borrow())?;
+        Some((b, i))
+    }
+
+    fn push(&mut self, h: u64, k: K, v: V) -> Pos {
+        let b = self.bucket_of(h);
+        self.buckets[b].push((k, v));
+        self.len += 1;
+        (b, self.buckets[b].len() - 1)
+    }
+
+    fn at(&self, (b, i): Pos) -> &(K, V) {
+        &self.buckets[b][i]
+    }
+
+    fn at_mut(&mut self, (b, i): Pos) -> &mut (K, V) {
+        &mut self.buckets[b][i]
+    }
+
+    fn remove_at(&mut self, (b, i): Pos) -> (K, V) {
+        self.len -= 1;
+        self.buckets[b].swap_remove(i)
+    }
+
+    fn crowded(&self, h: u64, max_bucket: usize) -> bool {
+        self.buckets[self.bucket_of(h)].len() >= max_bucket
+    }
+
+    fn walk_len(&self, (b, _): Pos) -> usize {
+        self.buckets[b].len()
+    }
+
+    fn histogram(&self, hist: &mut Vec<usize>) {
+        for b in &self.buckets {
+            add_count(hist, b.len());
         }
+    }
+
+    fn limit(&self) -> Option<usize> {
         None
     }
 
-    pub(super) fn bucket(&mut self, n: usize) -> Option<Vec<(K, V)>> {
+    fn take_bucket(&mut self, n: usize) -> Option<Vec<(K, V)>> {
         if n >= self.buckets.len() {
             return None;
         }
@@ -68,16 +138,38 @@ where
         Some(res)
     }
 
-    pub(super) fn set_buckets(&mut self, n: usize) {
-        for _ in self.buckets.len()..n {
-            self.buckets.push(Vec::new())
+    fn retain<F>(&mut self, f: &mut F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        for b in &mut self.buckets {
+            b.retain_mut(|(k, v)| f(k, v));
         }
+        self.len = self.buckets.iter().map(Vec::len).sum();
     }
-    pub(super) fn len(&self) -> usize {
-        self.len
+
+    fn clear(&mut self) {
+        for b in &mut self.buckets {
+            b.clear();
+        }
+        self.len = 0;
     }
 
-    pub(super) fn b_len(&self) -> usize {
-        self.buckets.len()
+    fn iter(&self) -> Self::Iter<'_> {
+        self.buckets.iter().flatten()
+    }
+
+    fn iter_mut(&mut self) -> Self::IterMut<'_> {
+        self.buckets.iter_mut().flatten()
+    }
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.buckets.into_iter().flatten()
+    }
+
+    fn drain(&mut self) -> Self::Drain<'_> {
+        self.len = 0;
+        let drain: DrainFn<'_, K, V> = |b| b.drain(..);
+        self.buckets.iter_mut().flat_map(drain)
     }
 }
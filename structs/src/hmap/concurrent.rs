@@ -0,0 +1,346 @@
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    ops::Deref,
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use rayon::prelude::*;
+
+use super::{
+    bucket_list::BucketList, entry::Slot, stats::FLOOD_LIMIT, Entry, HMap, RandomMHash, Table,
+};
+
+///An HMap split into shards, each behind its own RwLock,
+///so threads working on different shards never wait on each other.
+///
+///The shard is picked by the top bits of the key's hash.
+///Every shard shares the one hasher, and picks buckets from the low bits.
+pub struct ConcurrentHMap<K, V, S = RandomMHash, T = BucketList<K, V>> {
+    //64 - log2(shard count), so h >> shift is the shard
+    shift: u32,
+    hasher: S,
+    shards: Vec<RwLock<HMap<K, V, S, T>>>,
+}
+
+///A read lock on one shard, pointing at a single entry.
+///Writers to that shard wait until it is dropped.
+pub struct ShardRef<'a, K, V, S = RandomMHash, T = BucketList<K, V>> {
+    shard: RwLockReadGuard<'a, HMap<K, V, S, T>>,
+    slot: Slot,
+}
+
+impl<K, V> ConcurrentHMap<K, V>
+where
+    K: Hash + Eq,
+{
+    ///Four shards per rayon thread, so a few busy keys rarely share one
+    pub fn new() -> Self {
+        Self::with_shards(rayon::current_num_threads() * 4)
+    }
+
+    ///Each shard guards against floods the way HMap::new does
+    pub fn with_shards(n: usize) -> Self {
+        let mut res = Self::with_shards_and_hasher(n, RandomMHash::new());
+        for s in &mut res.shards {
+            s.get_mut()
+                .expect("shard lock poisoned")
+                .set_flood_limit(FLOOD_LIMIT);
+        }
+        res
+    }
+}
+
+impl<K, V, S, T> ConcurrentHMap<K, V, S, T>
+where
+    K: Hash + Eq,
+    S: BuildHasher + Clone,
+    T: Table<K, V>,
+{
+    ///n is rounded up to a power of two
+    pub fn with_shards_and_hasher(n: usize, hasher: S) -> Self {
+        let n = n.max(1).next_power_of_two();
+        ConcurrentHMap {
+            shift: 64 - n.trailing_zeros(),
+            shards: (0..n)
+                .map(|_| RwLock::new(HMap::with_hasher(hasher.clone())))
+                .collect(),
+            hasher,
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    fn shard_of<KR: Hash + ?Sized>(&self, kr: &KR) -> usize {
+        //a single shard would need a shift of 64, which overflows
+        self.hasher
+            .hash_one(kr)
+            .checked_shr(self.shift)
+            .unwrap_or(0) as usize
+    }
+
+    fn read(&self, n: usize) -> RwLockReadGuard<'_, HMap<K, V, S, T>> {
+        self.shards[n].read().expect("shard lock poisoned")
+    }
+
+    fn write(&self, n: usize) -> RwLockWriteGuard<'_, HMap<K, V, S, T>> {
+        self.shards[n].write().expect("shard lock poisoned")
+    }
+
+    pub fn insert(&self, k: K, v: V) {
+        self.write(self.shard_of(&k)).insert(k, v);
+    }
+
+    ///Holds the shard's read lock for as long as the ShardRef lives
+    pub fn get<KR>(&self, kr: &KR) -> Option<ShardRef<'_, K, V, S, T>>
+    where
+        K: Borrow<KR>,
+        KR: Hash + Eq + ?Sized,
+    {
+        let shard = self.read(self.shard_of(kr));
+        let slot = shard.find(kr)?;
+        Some(ShardRef { shard, slot })
+    }
+
+    ///Copies the value out, releasing the lock straight away
+    pub fn get_cloned<KR>(&self, kr: &KR) -> Option<V>
+    where
+        K: Borrow<KR>,
+        KR: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        self.read(self.shard_of(kr)).get(kr).cloned()
+    }
+
+    pub fn contains_key<KR>(&self, kr: &KR) -> bool
+    where
+        K: Borrow<KR>,
+        KR: Hash + Eq + ?Sized,
+    {
+        self.read(self.shard_of(kr)).get(kr).is_some()
+    }
+
+    pub fn remove<KR>(&self, kr: &KR) -> Option<V>
+    where
+        K: Borrow<KR>,
+        KR: Hash + Eq + ?Sized,
+    {
+        self.write(self.shard_of(kr)).remove(kr)
+    }
+
+    ///Runs f on the key's entry with its shard write locked,
+    ///so a read, change and write back can't be interleaved with another thread's.
+    ///f must not use this map again, as its shard is already locked.
+    pub fn with_entry<F, R>(&self, k: K, f: F) -> R
+    where
+        F: FnOnce(Entry<'_, K, V, S, T>) -> R,
+    {
+        let mut shard = self.write(self.shard_of(&k));
+        f(shard.entry(k))
+    }
+
+    ///Takes every shard's read lock in turn, so the total can be out of date
+    ///by the time it returns if other threads are writing.
+    pub fn len(&self) -> usize {
+        (0..self.shards.len()).map(|n| self.read(n).len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..self.shards.len()).all(|n| self.read(n).is_empty())
+    }
+
+    pub fn clear(&self) {
+        for n in 0..self.shards.len() {
+            self.write(n).clear();
+        }
+    }
+
+    pub fn into_inner(self) -> Vec<HMap<K, V, S, T>> {
+        self.shards
+            .into_iter()
+            .map(|s| s.into_inner().expect("shard lock poisoned"))
+            .collect()
+    }
+}
+
+impl<K, V, S, T> ConcurrentHMap<K, V, S, T>
+where
+    K: Hash + Eq + Send + Sync,
+    V: Send + Sync,
+    S: BuildHasher + Clone + Send + Sync,
+    T: Table<K, V> + Send + Sync,
+{
+    ///Copies the entries out in parallel, one shard per task.
+    ///Each shard is read locked only while it is being copied,
+    ///so this is not a snapshot of the whole map if other threads are writing.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = (K, V)> + '_
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.shards.par_iter().flat_map_iter(|s| {
+            let shard = s.read().expect("shard lock poisoned");
+            shard
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect::<Vec<_>>()
+        })
+    }
+
+    ///Calls f on every entry in parallel, without copying,
+    ///holding each shard's read lock while its entries are visited.
+    pub fn par_for_each<F>(&self, f: F)
+    where
+        F: Fn(&K, &V) + Send + Sync,
+    {
+        self.shards.par_iter().for_each(|s| {
+            let shard = s.read().expect("shard lock poisoned");
+            for (k, v) in shard.iter() {
+                f(k, v);
+            }
+        });
+    }
+}
+
+impl<K, V> Default for ConcurrentHMap<K, V>
+where
+    K: Hash + Eq,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, K, V, S, T> ShardRef<'a, K, V, S, T>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    pub fn key(&self) -> &K {
+        &self.shard.slot(self.slot).0
+    }
+
+    pub fn value(&self) -> &V {
+        &self.shard.slot(self.slot).1
+    }
+}
+
+impl<'a, K, V, S, T> Deref for ShardRef<'a, K, V, S, T>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        self.value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_basic() {
+        let cm = ConcurrentHMap::with_shards(5);
+        assert_eq!(cm.shard_count(), 8);
+        for x in 0..1000 {
+            cm.insert(x, x + 1);
+        }
+        assert_eq!(cm.len(), 1000);
+        assert_eq!(*cm.get(&10).unwrap(), 11);
+        assert_eq!(cm.get(&10).unwrap().key(), &10);
+        assert_eq!(cm.get_cloned(&999), Some(1000));
+        assert!(cm.get(&1000).is_none());
+        assert_eq!(cm.remove(&10), Some(11));
+        assert!(!cm.contains_key(&10));
+        //every shard should get a share of the keys
+        let shards = cm.into_inner();
+        assert!(shards.iter().all(|s| s.len() > 50));
+        assert_eq!(shards.iter().map(HMap::len).sum::<usize>(), 999);
+    }
+
+    #[test]
+    fn test_one_shard() {
+        let cm = ConcurrentHMap::with_shards(1);
+        cm.insert("a", 1);
+        assert_eq!(cm.get_cloned("a"), Some(1));
+        cm.clear();
+        assert!(cm.is_empty());
+    }
+
+    #[test]
+    fn test_many_writers() {
+        let cm = ConcurrentHMap::new();
+        let threads = 16;
+        let per = 5000;
+        thread::scope(|s| {
+            for t in 0..threads {
+                let cm = &cm;
+                s.spawn(move || {
+                    for x in 0..per {
+                        cm.insert(t * per + x, t);
+                        //readers and removers mixed in with the writes
+                        if x % 3 == 0 {
+                            assert_eq!(cm.remove(&(t * per + x)), Some(t));
+                        }
+                        if x > 0 {
+                            assert!(cm.get(&(t * per + x - 1)).is_some() || (x - 1) % 3 == 0);
+                        }
+                    }
+                });
+            }
+        });
+        let removed = (0..per).filter(|x| x % 3 == 0).count();
+        assert_eq!(cm.len(), threads * (per - removed));
+        for t in 0..threads {
+            assert_eq!(cm.get_cloned(&(t * per + 1)), Some(t));
+            assert_eq!(cm.get_cloned(&(t * per + 3)), None);
+        }
+    }
+
+    #[test]
+    fn test_entry_counters() {
+        //every thread bumps the same small set of counters
+        let cm: ConcurrentHMap<u32, u64> = ConcurrentHMap::with_shards(4);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for x in 0..10000 {
+                        cm.with_entry(x % 50, |e| *e.or_insert(0) += 1);
+                    }
+                });
+            }
+        });
+        assert_eq!(cm.len(), 50);
+        for x in 0..50 {
+            assert_eq!(cm.get_cloned(&x), Some(8 * 200));
+        }
+    }
+
+    #[test]
+    fn test_par_iter() {
+        let cm = ConcurrentHMap::new();
+        (0..10000u64)
+            .into_par_iter()
+            .for_each(|x| cm.insert(x, x * 2));
+        let total: u64 = cm.par_iter().map(|(_, v)| v).sum();
+        assert_eq!(total, (0..10000).map(|x| x * 2).sum());
+        let mut keys: Vec<u64> = cm.par_iter().map(|(k, _)| k).collect();
+        keys.sort();
+        assert_eq!(keys, (0..10000).collect::<Vec<_>>());
+
+        let count = std::sync::atomic::AtomicUsize::new(0);
+        cm.par_for_each(|k, v| {
+            assert_eq!(*v, k * 2);
+            count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        });
+        assert_eq!(count.into_inner(), 10000);
+    }
+}
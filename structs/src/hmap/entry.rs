@@ -0,0 +1,174 @@
+use std::hash::{BuildHasher, Hash};
+
+use super::{
+    bucket_list::BucketList,
+    table::{Pos, Table},
+    HMap,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Side {
+    Main,
+    Grow,
+}
+
+///Where an entry lives, so it can be reached again without hashing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Slot {
+    pub(super) side: Side,
+    pub(super) pos: Pos,
+}
+
+impl Slot {
+    pub(super) fn new(side: Side, pos: Pos) -> Self {
+        Slot { side, pos }
+    }
+}
+
+pub enum Entry<'a, K, V, S, T = BucketList<K, V>> {
+    Occupied(OccupiedEntry<'a, K, V, S, T>),
+    Vacant(VacantEntry<'a, K, V, S, T>),
+}
+
+pub struct OccupiedEntry<'a, K, V, S, T = BucketList<K, V>> {
+    map: &'a mut HMap<K, V, S, T>,
+    slot: Slot,
+}
+
+///Holds the key's hash, so inserting does not need to work it out again.
+pub struct VacantEntry<'a, K, V, S, T = BucketList<K, V>> {
+    map: &'a mut HMap<K, V, S, T>,
+    key: K,
+    hash: u64,
+}
+
+impl<'a, K, V, S, T> Entry<'a, K, V, S, T>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    pub(super) fn new(map: &'a mut HMap<K, V, S, T>, key: K) -> Self {
+        let hash = map.hash(&key);
+        if let Some(p) = map.main.find(hash, &key) {
+            return Entry::Occupied(OccupiedEntry::new(map, Slot::new(Side::Main, p)));
+        }
+        if let Some(p) = map.grow.find(hash, &key) {
+            return Entry::Occupied(OccupiedEntry::new(map, Slot::new(Side::Grow, p)));
+        }
+        Entry::Vacant(VacantEntry { map, key, hash })
+    }
+
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(e) => e.key(),
+            Entry::Vacant(e) => e.key(),
+        }
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default),
+        }
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(default()),
+        }
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Entry::Occupied(e) = &mut self {
+            f(e.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, K, V, S, T> Entry<'a, K, V, S, T>
+where
+    K: Hash + Eq,
+    V: Default,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    pub fn or_default(self) -> &'a mut V {
+        self.or_insert_with(V::default)
+    }
+}
+
+impl<'a, K, V, S, T> OccupiedEntry<'a, K, V, S, T>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    fn new(map: &'a mut HMap<K, V, S, T>, slot: Slot) -> Self {
+        OccupiedEntry { map, slot }
+    }
+
+    pub fn key(&self) -> &K {
+        &self.map.slot(self.slot).0
+    }
+
+    pub fn get(&self) -> &V {
+        &self.map.slot(self.slot).1
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.map.slot_mut(self.slot).1
+    }
+
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.map.slot_mut(self.slot).1
+    }
+
+    ///Replaces the value, returning the old one
+    pub fn insert(&mut self, v: V) -> V {
+        std::mem::replace(self.get_mut(), v)
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    pub fn remove_entry(self) -> (K, V) {
+        let Slot { side, pos } = self.slot;
+        match side {
+            Side::Main => self.map.main.remove_at(pos),
+            Side::Grow => self.map.grow.remove_at(pos),
+        }
+    }
+}
+
+impl<'a, K, V, S, T> VacantEntry<'a, K, V, S, T>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    ///Inserts the value, moving a bucket across the same way HMap::insert does.
+    ///The move happens first, so the new entry's position stays put.
+    pub fn insert(self, v: V) -> &'a mut V {
+        let map = self.map;
+        map.step_migration(self.hash);
+        let slot = if map.n_moved > 0 {
+            Slot::new(Side::Grow, map.grow.push(self.hash, self.key, v))
+        } else {
+            Slot::new(Side::Main, map.main.push(self.hash, self.key, v))
+        };
+        let slot = map.check_flood(slot);
+        &mut map.slot_mut(slot).1
+    }
+}
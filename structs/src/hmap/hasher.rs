@@ -1,5 +1,9 @@
-use std::hash::{Hash, Hasher};
+use std::{
+    collections::hash_map::{DefaultHasher, RandomState},
+    hash::{BuildHasher, BuildHasherDefault, Hash, Hasher},
+};
 
+#[derive(Default)]
 pub struct MHash {
     prev: u8,
     n: u128,
@@ -9,7 +13,7 @@ impl Hasher for MHash {
     fn write(&mut self, dt: &[u8]) {
         for d in dt {
             self.n = ((self.n + 11) * (*d as u128 + 13) + ((d ^ self.prev) as u128))
-                % (std::u64::MAX as u128);
+                % (u64::MAX as u128);
             self.prev = *d;
         }
     }
@@ -20,12 +24,120 @@ impl Hasher for MHash {
 }
 
 pub fn hash<T: Hash>(seed: u64, t: T) -> u64 {
-    let mut h = MHash { n: 0, prev: 0 };
-    h.write_u64(seed);
-    t.hash(&mut h);
-    h.finish()
+    RandomMHash::with_seed(seed).hash_one(t)
 }
 
+///The default BuildHasher for HMap.
+///Each map gets its own random seed, which is fed to MHash before the key.
+///
+///MHash is quick but easy to find collisions for whatever the seed,
+///so once reseeded this switches to std's keyed SipHash for good.
+#[derive(Debug, Clone)]
+pub struct RandomMHash {
+    seed: u64,
+    keyed: Option<RandomState>,
+}
+
+///What RandomMHash builds, MHash until it has been reseeded
+pub enum RandomHasher {
+    M(MHash),
+    Sip(DefaultHasher),
+}
+
+///A BuildHasher that can change its keys,
+///so a map whose keys all collide can rehash them somewhere new.
+pub trait Reseed: BuildHasher {
+    fn reseed(&mut self);
+}
+
+impl RandomMHash {
+    pub fn new() -> Self {
+        Self::with_seed(rand::random())
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        RandomMHash { seed, keyed: None }
+    }
+
+    ///True once reseeded onto SipHash
+    pub fn is_keyed(&self) -> bool {
+        self.keyed.is_some()
+    }
+}
+
+impl Default for RandomMHash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BuildHasher for RandomMHash {
+    type Hasher = RandomHasher;
+
+    fn build_hasher(&self) -> RandomHasher {
+        match &self.keyed {
+            Some(rs) => RandomHasher::Sip(rs.build_hasher()),
+            None => {
+                let mut h = MHash::default();
+                h.write_u64(self.seed);
+                RandomHasher::M(h)
+            }
+        }
+    }
+}
+
+impl Hasher for RandomHasher {
+    fn write(&mut self, dt: &[u8]) {
+        match self {
+            RandomHasher::M(h) => h.write(dt),
+            RandomHasher::Sip(h) => h.write(dt),
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        match self {
+            RandomHasher::M(h) => h.finish(),
+            RandomHasher::Sip(h) => h.finish(),
+        }
+    }
+}
+
+impl Reseed for RandomMHash {
+    fn reseed(&mut self) {
+        self.seed = rand::random();
+        self.keyed = Some(RandomState::new());
+    }
+}
+
+impl Reseed for RandomState {
+    fn reseed(&mut self) {
+        *self = RandomState::new();
+    }
+}
+
+///A BuildHasher a map can make for itself, as Default, collect and deserialize do.
+///One that can Reseed hands its reseed back from reseeder,
+///so those maps guard against floods the way HMap::new does.
+pub trait MapHasher: BuildHasher + Default {
+    fn reseeder() -> Option<fn(&mut Self)> {
+        None
+    }
+}
+
+impl MapHasher for RandomMHash {
+    fn reseeder() -> Option<fn(&mut Self)> {
+        Some(Self::reseed)
+    }
+}
+
+impl MapHasher for RandomState {
+    fn reseeder() -> Option<fn(&mut Self)> {
+        Some(Self::reseed)
+    }
+}
+
+impl<H: Hasher + Default> MapHasher for BuildHasherDefault<H> {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -38,6 +150,24 @@ mod tests {
         assert!(hash(55, "abc") != hash(55, "cba"));
     }
 
+    #[test]
+    fn test_builder_matches_hash() {
+        let b = RandomMHash::with_seed(55);
+        assert_eq!(b.hash_one("cat"), hash(55, "cat"));
+        assert_eq!(b.hash_one(20), hash(55, 20));
+        assert!(RandomMHash::new().hash_one(20) != RandomMHash::new().hash_one(20));
+    }
+
+    #[test]
+    fn test_reseed() {
+        let mut b = RandomMHash::with_seed(55);
+        assert!(!b.is_keyed());
+        b.reseed();
+        assert!(b.is_keyed());
+        assert!(b.hash_one("cat") != hash(55, "cat"));
+        assert_eq!(b.hash_one("cat"), b.clone().hash_one("cat"));
+    }
+
     #[test]
     fn test_numbers() {
         let mut prev = 0;
@@ -0,0 +1,173 @@
+use std::iter::Chain;
+
+use super::{bucket_list::BucketList, table::Table};
+
+//Entries live in exactly one of main or grow, so chaining the two
+//visits everything once even part way through a migration.
+type Both<I> = Chain<I, I>;
+
+pub struct Iter<'a, K: 'a, V: 'a, T: Table<K, V> + 'a = BucketList<K, V>> {
+    inner: Both<T::Iter<'a>>,
+    left: usize,
+}
+
+pub struct IterMut<'a, K: 'a, V: 'a, T: Table<K, V> + 'a = BucketList<K, V>> {
+    inner: Both<T::IterMut<'a>>,
+    left: usize,
+}
+
+pub struct IntoIter<K, V, T: Table<K, V> = BucketList<K, V>> {
+    inner: Both<T::IntoIter>,
+    left: usize,
+}
+
+///Removes entries as it goes.  Anything not yielded is dropped with the Drain.
+pub struct Drain<'a, K: 'a, V: 'a, T: Table<K, V> + 'a = BucketList<K, V>> {
+    inner: Both<T::Drain<'a>>,
+    left: usize,
+}
+
+pub struct Keys<'a, K: 'a, V: 'a, T: Table<K, V> + 'a = BucketList<K, V>>(
+    pub(super) Iter<'a, K, V, T>,
+);
+pub struct Values<'a, K: 'a, V: 'a, T: Table<K, V> + 'a = BucketList<K, V>>(
+    pub(super) Iter<'a, K, V, T>,
+);
+pub struct ValuesMut<'a, K: 'a, V: 'a, T: Table<K, V> + 'a = BucketList<K, V>>(
+    pub(super) IterMut<'a, K, V, T>,
+);
+
+impl<'a, K, V, T: Table<K, V>> Iter<'a, K, V, T> {
+    pub(super) fn new(main: &'a T, grow: &'a T, left: usize) -> Self {
+        let inner = main.iter().chain(grow.iter());
+        Iter { inner, left }
+    }
+}
+
+impl<'a, K, V, T: Table<K, V>> IterMut<'a, K, V, T> {
+    pub(super) fn new(main: &'a mut T, grow: &'a mut T, left: usize) -> Self {
+        let inner = main.iter_mut().chain(grow.iter_mut());
+        IterMut { inner, left }
+    }
+}
+
+impl<K, V, T: Table<K, V>> IntoIter<K, V, T> {
+    pub(super) fn new(main: T, grow: T, left: usize) -> Self {
+        let inner = main.into_iter().chain(grow.into_iter());
+        IntoIter { inner, left }
+    }
+}
+
+impl<'a, K, V, T: Table<K, V>> Drain<'a, K, V, T> {
+    pub(super) fn new(main: &'a mut T, grow: &'a mut T, left: usize) -> Self {
+        let inner = main.drain().chain(grow.drain());
+        Drain { inner, left }
+    }
+}
+
+impl<'a, K, V, T: Table<K, V>> Iterator for Iter<'a, K, V, T> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.inner.next()?;
+        self.left -= 1;
+        Some((k, v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.left, Some(self.left))
+    }
+}
+
+impl<'a, K, V, T: Table<K, V>> Iterator for IterMut<'a, K, V, T> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.inner.next()?;
+        self.left -= 1;
+        Some((&*k, v))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.left, Some(self.left))
+    }
+}
+
+impl<K, V, T: Table<K, V>> Iterator for IntoIter<K, V, T> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let res = self.inner.next()?;
+        self.left -= 1;
+        Some(res)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.left, Some(self.left))
+    }
+}
+
+impl<'a, K, V, T: Table<K, V>> Iterator for Drain<'a, K, V, T> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let res = self.inner.next()?;
+        self.left -= 1;
+        Some(res)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.left, Some(self.left))
+    }
+}
+
+impl<'a, K, V, T: Table<K, V>> Drop for Drain<'a, K, V, T> {
+    fn drop(&mut self) {
+        //Buckets not yet reached would otherwise stay in the map
+        self.inner.by_ref().for_each(drop);
+    }
+}
+
+impl<'a, K, V, T: Table<K, V>> Iterator for Keys<'a, K, V, T> {
+    type Item = &'a K;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(k, _)| k)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K, V, T: Table<K, V>> Iterator for Values<'a, K, V, T> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K, V, T: Table<K, V>> Iterator for ValuesMut<'a, K, V, T> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(_, v)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, K, V, T: Table<K, V>> ExactSizeIterator for Iter<'a, K, V, T> {}
+impl<'a, K, V, T: Table<K, V>> ExactSizeIterator for IterMut<'a, K, V, T> {}
+impl<K, V, T: Table<K, V>> ExactSizeIterator for IntoIter<K, V, T> {}
+impl<'a, K, V, T: Table<K, V>> ExactSizeIterator for Drain<'a, K, V, T> {}
+impl<'a, K, V, T: Table<K, V>> ExactSizeIterator for Keys<'a, K, V, T> {}
+impl<'a, K, V, T: Table<K, V>> ExactSizeIterator for Values<'a, K, V, T> {}
+impl<'a, K, V, T: Table<K, V>> ExactSizeIterator for ValuesMut<'a, K, V, T> {}
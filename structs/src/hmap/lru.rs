@@ -0,0 +1,551 @@
+use std::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+    ops::{Deref, DerefMut},
+};
+
+use super::{stats::FLOOD_LIMIT, HMap, RandomMHash};
+
+//Marks the end of the recency list
+const NIL: usize = usize::MAX;
+
+type Weigh<K, V> = Box<dyn Fn(&K, &V) -> usize>;
+type OnEvict<K, V> = Box<dyn FnMut(K, V)>;
+
+struct Node<K, V> {
+    kv: Option<(K, V)>,
+    ///What the entry weighed when last weighed, as counted in the map's weight
+    weight: usize,
+    prev: usize,
+    next: usize,
+}
+
+///A bounded HMap that drops the least recently used entries to stay in budget.
+///
+///The map holds each key's index into a slab of nodes,
+///and the nodes link into a recency list, most recent at the head.
+///Keys are stored in both, so must be Clone.
+pub struct LruHMap<K, V, S = RandomMHash> {
+    map: HMap<K, usize, S>,
+    nodes: Vec<Node<K, V>>,
+    free: Vec<usize>,
+    head: usize,
+    tail: usize,
+    weight: usize,
+    budget: usize,
+    weigh: Weigh<K, V>,
+    on_evict: Option<OnEvict<K, V>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl<K, V> LruHMap<K, V>
+where
+    K: Hash + Eq + Clone,
+{
+    ///Holds at most max entries
+    pub fn new(max: usize) -> Self {
+        let mut res = Self::with_hasher(max, RandomMHash::new());
+        res.map.set_flood_limit(FLOOD_LIMIT);
+        res
+    }
+
+    ///Holds entries until their total weight would pass budget,
+    ///weigh giving the size of one entry, in bytes or any other unit.
+    pub fn with_budget<F>(budget: usize, weigh: F) -> Self
+    where
+        F: Fn(&K, &V) -> usize + 'static,
+    {
+        let mut res = Self::new(budget);
+        res.weigh = Box::new(weigh);
+        res
+    }
+}
+
+impl<K, V, S> LruHMap<K, V, S>
+where
+    K: Hash + Eq + Clone,
+    S: BuildHasher,
+{
+    pub fn with_hasher(max: usize, hasher: S) -> Self {
+        LruHMap {
+            map: HMap::with_hasher(hasher),
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: NIL,
+            tail: NIL,
+            weight: 0,
+            budget: max,
+            weigh: Box::new(|_, _| 1),
+            on_evict: None,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    ///Called with each entry dropped to make room.
+    ///Entries that are removed, replaced or cleared are not passed to it.
+    pub fn set_on_evict<F>(&mut self, f: F)
+    where
+        F: FnMut(K, V) + 'static,
+    {
+        self.on_evict = Some(Box::new(f));
+    }
+
+    fn node(&self, i: usize) -> &(K, V) {
+        self.nodes[i].kv.as_ref().expect("freed lru node")
+    }
+
+    fn node_mut(&mut self, i: usize) -> &mut (K, V) {
+        self.nodes[i].kv.as_mut().expect("freed lru node")
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let Node { prev, next, .. } = self.nodes[i];
+        match prev {
+            NIL => self.head = next,
+            p => self.nodes[p].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            n => self.nodes[n].prev = prev,
+        }
+    }
+
+    fn push_front(&mut self, i: usize) {
+        self.nodes[i].prev = NIL;
+        self.nodes[i].next = self.head;
+        match self.head {
+            NIL => self.tail = i,
+            h => self.nodes[h].prev = i,
+        }
+        self.head = i;
+    }
+
+    fn touch(&mut self, i: usize) {
+        if self.head != i {
+            self.unlink(i);
+            self.push_front(i);
+        }
+    }
+
+    ///Unlinks node i and frees its slot, returning the entry
+    fn take(&mut self, i: usize) -> (K, V) {
+        self.unlink(i);
+        self.free.push(i);
+        self.weight -= self.nodes[i].weight;
+        self.nodes[i].kv.take().expect("freed lru node")
+    }
+
+    ///Weighs node i again after its value changed, then evicts as insert would
+    fn reweigh(&mut self, i: usize) {
+        let (k, v) = self.node(i);
+        let w = (self.weigh)(k, v);
+        self.weight = self.weight - self.nodes[i].weight + w;
+        self.nodes[i].weight = w;
+        if w > self.budget {
+            let (k, v) = self.take(i);
+            self.map.remove(&k);
+            self.evicted(k, v);
+        } else {
+            self.evict_to_budget();
+        }
+    }
+
+    fn evict_to_budget(&mut self) {
+        while self.weight > self.budget && self.tail != NIL {
+            let (k, v) = self.take(self.tail);
+            self.map.remove(&k);
+            self.evicted(k, v);
+        }
+    }
+
+    fn evicted(&mut self, k: K, v: V) {
+        if let Some(f) = &mut self.on_evict {
+            f(k, v);
+        }
+    }
+
+    ///Inserts or replaces the value, making it the most recently used.
+    ///An entry heavier than the whole budget is evicted straight away,
+    ///leaving the others be, though it still replaces any value k had.
+    pub fn insert(&mut self, k: K, v: V) {
+        if (self.weigh)(&k, &v) > self.budget {
+            self.remove(&k);
+            self.evicted(k, v);
+            return;
+        }
+        if let Some(&i) = self.map.get(&k) {
+            self.node_mut(i).1 = v;
+            self.touch(i);
+            return self.reweigh(i);
+        }
+        let weight = (self.weigh)(&k, &v);
+        self.weight += weight;
+        let node = Node {
+            kv: Some((k.clone(), v)),
+            weight,
+            prev: NIL,
+            next: NIL,
+        };
+        let i = match self.free.pop() {
+            Some(i) => {
+                self.nodes[i] = node;
+                i
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        self.push_front(i);
+        self.map.insert(k, i);
+        self.evict_to_budget();
+    }
+
+    ///Looks the key up, counting a hit or miss and marking it most recently used
+    pub fn get<KR>(&mut self, kr: &KR) -> Option<&V>
+    where
+        K: Borrow<KR>,
+        KR: Hash + Eq + ?Sized,
+    {
+        let i = self.find(kr)?;
+        Some(&self.node(i).1)
+    }
+
+    ///As get, but the value can be changed through the guard,
+    ///which weighs the entry again when dropped and evicts as insert would
+    pub fn get_mut<KR>(&mut self, kr: &KR) -> Option<LruValueMut<'_, K, V, S>>
+    where
+        K: Borrow<KR>,
+        KR: Hash + Eq + ?Sized,
+    {
+        let i = self.find(kr)?;
+        Some(LruValueMut { lru: self, i })
+    }
+
+    ///The node holding the key, counting a hit or miss and marking it most recently used
+    fn find<KR>(&mut self, kr: &KR) -> Option<usize>
+    where
+        K: Borrow<KR>,
+        KR: Hash + Eq + ?Sized,
+    {
+        match self.map.get(kr) {
+            Some(&i) => {
+                self.hits += 1;
+                self.touch(i);
+                Some(i)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    ///Looks the key up without changing its recency or the counters
+    pub fn peek<KR>(&self, kr: &KR) -> Option<&V>
+    where
+        K: Borrow<KR>,
+        KR: Hash + Eq + ?Sized,
+    {
+        let i = *self.map.get(kr)?;
+        Some(&self.node(i).1)
+    }
+
+    pub fn contains_key<KR>(&self, kr: &KR) -> bool
+    where
+        K: Borrow<KR>,
+        KR: Hash + Eq + ?Sized,
+    {
+        self.map.get(kr).is_some()
+    }
+
+    ///Returns the cached value, or works it out with f and caches it.
+    ///None if f's value is heavier than the whole budget, so was evicted straight away.
+    pub fn get_or_insert_with<F>(&mut self, k: K, f: F) -> Option<&V>
+    where
+        F: FnOnce() -> V,
+    {
+        if self.get(&k).is_none() {
+            self.insert(k.clone(), f());
+        }
+        let i = *self.map.get(&k)?;
+        Some(&self.node(i).1)
+    }
+
+    pub fn remove<KR>(&mut self, kr: &KR) -> Option<V>
+    where
+        K: Borrow<KR>,
+        KR: Hash + Eq + ?Sized,
+    {
+        let i = self.map.remove(kr)?;
+        Some(self.take(i).1)
+    }
+
+    ///The least recently used entry, which is the next to be evicted
+    pub fn peek_lru(&self) -> Option<(&K, &V)> {
+        match self.tail {
+            NIL => None,
+            t => {
+                let (k, v) = self.node(t);
+                Some((k, v))
+            }
+        }
+    }
+
+    ///Changes the budget, evicting at once if the map is now over it
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict_to_budget();
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    ///Total weight of the entries, the same as len unless built with_budget
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.nodes.clear();
+        self.free.clear();
+        self.head = NIL;
+        self.tail = NIL;
+        self.weight = 0;
+    }
+
+    ///Entries from most to least recently used
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        let mut i = self.head;
+        std::iter::from_fn(move || {
+            if i == NIL {
+                return None;
+            }
+            let (k, v) = self.node(i);
+            i = self.nodes[i].next;
+            Some((k, v))
+        })
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.hits = 0;
+        self.misses = 0;
+    }
+}
+
+///A value borrowed mutably from an LruHMap by get_mut.
+///Once dropped, the entry is weighed again, and evicted with others as need be.
+pub struct LruValueMut<'a, K, V, S>
+where
+    K: Hash + Eq + Clone,
+    S: BuildHasher,
+{
+    lru: &'a mut LruHMap<K, V, S>,
+    i: usize,
+}
+
+impl<K, V, S> Deref for LruValueMut<'_, K, V, S>
+where
+    K: Hash + Eq + Clone,
+    S: BuildHasher,
+{
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        &self.lru.node(self.i).1
+    }
+}
+
+impl<K, V, S> DerefMut for LruValueMut<'_, K, V, S>
+where
+    K: Hash + Eq + Clone,
+    S: BuildHasher,
+{
+    fn deref_mut(&mut self) -> &mut V {
+        &mut self.lru.node_mut(self.i).1
+    }
+}
+
+impl<K, V, S> Drop for LruValueMut<'_, K, V, S>
+where
+    K: Hash + Eq + Clone,
+    S: BuildHasher,
+{
+    fn drop(&mut self) {
+        self.lru.reweigh(self.i);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LruHMap;
+    use std::{cell::RefCell, rc::Rc};
+
+    fn keys(lru: &LruHMap<i32, i32>) -> Vec<i32> {
+        lru.iter().map(|(k, _)| *k).collect()
+    }
+
+    #[test]
+    fn test_evicts_least_recent() {
+        let evicted = Rc::new(RefCell::new(Vec::new()));
+        let mut lru = LruHMap::new(3);
+        let ev = evicted.clone();
+        lru.set_on_evict(move |k, v| ev.borrow_mut().push((k, v)));
+
+        for x in 0..3 {
+            lru.insert(x, x * 10);
+        }
+        assert_eq!(keys(&lru), vec![2, 1, 0]);
+        //reading 0 saves it, so 1 goes next
+        assert_eq!(lru.get(&0), Some(&0));
+        lru.insert(3, 30);
+        assert_eq!(keys(&lru), vec![3, 0, 2]);
+        assert_eq!(*evicted.borrow(), vec![(1, 10)]);
+        assert_eq!(lru.peek(&1), None);
+
+        //peek does not save 2
+        assert_eq!(lru.peek(&2), Some(&20));
+        lru.insert(4, 40);
+        assert_eq!(*evicted.borrow(), vec![(1, 10), (2, 20)]);
+
+        //replacing refreshes without evicting
+        lru.insert(0, 1);
+        assert_eq!(keys(&lru), vec![0, 4, 3]);
+        assert_eq!(lru.len(), 3);
+        assert_eq!(evicted.borrow().len(), 2);
+
+        lru.set_budget(1);
+        assert_eq!(keys(&lru), vec![0]);
+        assert_eq!(evicted.borrow().len(), 4);
+    }
+
+    #[test]
+    fn test_remove_reuses_nodes() {
+        let mut lru = LruHMap::new(100);
+        for x in 0..50 {
+            lru.insert(x, x);
+        }
+        for x in (0..50).step_by(2) {
+            assert_eq!(lru.remove(&x), Some(x));
+        }
+        assert_eq!(lru.remove(&0), None);
+        for x in 100..125 {
+            lru.insert(x, x);
+        }
+        assert_eq!(lru.nodes.len(), 50);
+        assert_eq!(lru.len(), 50);
+        assert_eq!(lru.peek_lru(), Some((&1, &1)));
+        let mut all = keys(&lru);
+        all.sort();
+        let expect: Vec<i32> = (1..50).step_by(2).chain(100..125).collect();
+        assert_eq!(all, expect);
+        lru.clear();
+        assert!(lru.is_empty());
+        assert_eq!(lru.peek_lru(), None);
+    }
+
+    #[test]
+    fn test_byte_budget() {
+        let mut lru = LruHMap::with_budget(20, |k: &String, v: &String| k.len() + v.len());
+        lru.insert("a".to_string(), "12345".to_string());
+        lru.insert("b".to_string(), "12345".to_string());
+        lru.insert("c".to_string(), "12345".to_string());
+        assert_eq!(lru.weight(), 18);
+        lru.insert("d".to_string(), "123".to_string());
+        assert_eq!(lru.weight(), 16);
+        assert!(!lru.contains_key("a"));
+        //growing a value can push others out
+        lru.insert("d".to_string(), "1234567".to_string());
+        assert_eq!(lru.weight(), 20);
+        lru.insert("d".to_string(), "12345678".to_string());
+        assert_eq!(lru.weight(), 15);
+        assert!(!lru.contains_key("b"));
+        //too big to keep at all, but the rest stay
+        let evicted = Rc::new(RefCell::new(Vec::new()));
+        let ev = evicted.clone();
+        lru.set_on_evict(move |k, _| ev.borrow_mut().push(k));
+        lru.insert("e".to_string(), "x".repeat(30));
+        assert_eq!(lru.len(), 2);
+        assert_eq!(lru.weight(), 15);
+        assert_eq!(*evicted.borrow(), vec!["e".to_string()]);
+        assert!(lru
+            .get_or_insert_with("f".to_string(), || "y".repeat(30))
+            .is_none());
+        assert_eq!(lru.len(), 2);
+
+        let mut none = LruHMap::new(0);
+        assert_eq!(none.get_or_insert_with(1, || 2), None);
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn test_hits_and_memo() {
+        let mut lru = LruHMap::new(10);
+        let mut calls = 0;
+        for x in [1, 2, 1, 1, 3, 2] {
+            lru.get_or_insert_with(x, || {
+                calls += 1;
+                x * x
+            });
+        }
+        assert_eq!(calls, 3);
+        assert_eq!(lru.hits(), 3);
+        assert_eq!(lru.misses(), 3);
+        assert_eq!(lru.get(&9), None);
+        assert_eq!(lru.misses(), 4);
+        *lru.get_mut(&3).unwrap() += 1;
+        assert_eq!(lru.peek(&3), Some(&10));
+        assert_eq!(lru.weight(), 3);
+        lru.reset_stats();
+        assert_eq!((lru.hits(), lru.misses()), (0, 0));
+    }
+
+    #[test]
+    fn test_get_mut_reweighs() {
+        let mut lru = LruHMap::with_budget(10, |_: &i32, v: &String| v.len());
+        lru.insert(1, "a".to_string());
+        lru.insert(2, "bb".to_string());
+        lru.insert(3, "ccc".to_string());
+        assert_eq!(lru.weight(), 6);
+        //growing a value past the room left pushes out the least recent
+        lru.get_mut(&3).unwrap().push_str("ccccc");
+        assert_eq!(lru.weight(), 10);
+        assert!(!lru.contains_key(&1));
+        //and removing it takes off what it weighs now
+        assert_eq!(lru.remove(&3).unwrap().len(), 8);
+        assert_eq!(lru.weight(), 2);
+
+        //grown past the whole budget, it alone is evicted
+        let evicted = Rc::new(RefCell::new(Vec::new()));
+        let ev = evicted.clone();
+        lru.set_on_evict(move |k, _| ev.borrow_mut().push(k));
+        lru.insert(4, "d".to_string());
+        lru.get_mut(&4).unwrap().push_str(&"d".repeat(50));
+        assert_eq!(*evicted.borrow(), vec![4]);
+        assert_eq!(lru.weight(), 2);
+        assert_eq!(lru.peek(&2).map(|v| v.as_str()), Some("bb"));
+        //shrinking gives the weight back
+        lru.get_mut(&2).unwrap().clear();
+        assert_eq!(lru.weight(), 0);
+    }
+}
@@ -1,61 +1,297 @@
-use self::bucket_list::{BucketList, BSIZE};
-use std::{borrow::Borrow, hash::Hash};
+use self::bucket_list::MAX_BUCKET;
+use self::entry::{Side, Slot};
+use self::stats::FLOOD_LIMIT;
+use std::{
+    borrow::Borrow,
+    fmt,
+    hash::{BuildHasher, Hash},
+    marker::PhantomData,
+    ops::Index,
+};
 
 mod bucket_list;
+mod concurrent;
+mod entry;
 mod hasher;
+mod iter;
+mod lru;
+mod open_table;
+pub mod quality;
+mod ser;
+mod set;
+mod stats;
+mod table;
 
-pub use hasher::hash;
+pub use bucket_list::BucketList;
+pub use concurrent::{ConcurrentHMap, ShardRef};
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use hasher::{hash, MHash, MapHasher, RandomHasher, RandomMHash, Reseed};
+pub use iter::{Drain, IntoIter, Iter, IterMut, Keys, Values, ValuesMut};
+pub use lru::{LruHMap, LruValueMut};
+pub use open_table::OpenTable;
+pub use ser::Sorted;
+pub use set::{Difference, HSet, Intersection, SetIntoIter, SetIter, SymmetricDifference, Union};
+pub use stats::HMapStats;
+pub use table::Table;
 
-#[derive(Debug)]
-pub struct HMap<K, V> {
+///main and grow share the one hasher, so a key hashes once per lookup
+///and only the bucket count differs between them.
+///
+///T picks how each half stores its entries,
+///chained BucketLists by default or an OpenTable.
+///
+///A hasher that can Reseed lets the map defend itself against keys chosen
+///to collide, see set_flood_limit.
+#[derive(Clone)]
+pub struct HMap<K, V, S = RandomMHash, T = BucketList<K, V>> {
     n_moved: usize,
-    main: BucketList<K, V>,
-    grow: BucketList<K, V>,
+    //bucket count for the next migration, 0 means double
+    resize_to: usize,
+    max_bucket: usize,
+    hasher: S,
+    main: T,
+    grow: T,
+    //set along with flood_limit when S can reseed
+    reseed: Option<fn(&mut S)>,
+    flood_limit: usize,
+    reseeds: usize,
+    kv: PhantomData<(K, V)>,
 }
 
+///An HMap using open addressing rather than chained buckets
+pub type OpenHMap<K, V, S = RandomMHash> = HMap<K, V, S, OpenTable<K, V>>;
+
 impl<K, V> HMap<K, V>
 where
     K: Hash + Eq,
 {
     pub fn new() -> Self {
+        Self::with_default_hasher(0)
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        Self::with_default_hasher(cap)
+    }
+}
+
+impl<K, V, S, T> HMap<K, V, S, T>
+where
+    K: Hash + Eq,
+    S: MapHasher,
+    T: Table<K, V>,
+{
+    ///Sized for cap with a fresh S, guarding against floods if S can reseed
+    fn with_default_hasher(cap: usize) -> Self {
+        let mut res = Self::with_capacity_and_hasher(cap, S::default());
+        if let Some(reseed) = S::reseeder() {
+            res.reseed = Some(reseed);
+            res.flood_limit = FLOOD_LIMIT;
+        }
+        res
+    }
+}
+
+impl<K, V, S, T> HMap<K, V, S, T>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    pub fn with_hasher(hasher: S) -> Self {
         HMap {
             n_moved: 0,
-            main: BucketList::new(),
-            grow: BucketList::new(),
+            resize_to: 0,
+            max_bucket: MAX_BUCKET,
+            hasher,
+            main: T::new(),
+            grow: T::new(),
+            reseed: None,
+            flood_limit: 0,
+            reseeds: 0,
+            kv: PhantomData,
         }
     }
 
-    pub fn insert(&mut self, k: K, v: V) {
-        if let Some(iv) = self.main.get_mut(&k) {
-            *iv = v;
-        } else if let Some(iv) = self.grow.get_mut(&k) {
-            *iv = v;
+    ///Starts with enough buckets that filling to cap
+    ///should not need to move anything.
+    pub fn with_capacity_and_hasher(cap: usize, hasher: S) -> Self {
+        let mut res = Self::with_hasher(hasher);
+        res.main.set_buckets(res.buckets_for(cap));
+        res
+    }
+
+    pub fn max_bucket(&self) -> usize {
+        self.max_bucket
+    }
+
+    ///Sets how long a bucket may get before the map grows.
+    ///Larger values use less memory but make lookups walk further.
+    ///An OpenTable grows on its load instead, so ignores this.
+    pub fn set_max_bucket(&mut self, n: usize) {
+        assert!(n > 0, "max_bucket must be at least 1");
+        self.max_bucket = n;
+    }
+
+    fn buckets_for(&self, n: usize) -> usize {
+        T::buckets_for(n, self.max_bucket)
+    }
+
+    ///The bucket count the map has, or is moving to
+    fn target_buckets(&self) -> usize {
+        if self.resize_to > 0 {
+            self.resize_to
         } else if self.n_moved > 0 {
-            self.grow.push(k, v);
-            self.move_bucket();
-        } else if self.main.push(k, v) > BSIZE / 2 {
+            self.grow.b_len()
+        } else {
+            self.main.b_len()
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        T::capacity_of(self.target_buckets(), self.max_bucket)
+    }
+
+    ///Makes room for at least additional more entries.
+    ///The buckets are moved across over the following inserts,
+    ///as with normal growth, rather than all at once.
+    pub fn reserve(&mut self, additional: usize) {
+        let n = self.buckets_for(self.len() + additional);
+        if n > self.target_buckets() {
+            self.resize(n);
+        }
+    }
+
+    ///Moves the entries into as few buckets as their count needs.
+    ///Like reserve, the work is spread over the following inserts.
+    pub fn shrink_to_fit(&mut self) {
+        let n = self.buckets_for(self.len());
+        if n < self.target_buckets() {
+            self.resize(n);
+        }
+    }
+
+    ///Starts a migration to n buckets, or queues it behind the current one
+    fn resize(&mut self, n: usize) {
+        self.resize_to = n;
+        if self.n_moved == 0 {
             self.move_bucket();
         }
     }
 
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    fn hash<Q: Hash + ?Sized>(&self, q: &Q) -> u64 {
+        self.hasher.hash_one(q)
+    }
+
+    pub fn insert(&mut self, k: K, v: V) {
+        match self.entry(k) {
+            Entry::Occupied(mut e) => {
+                e.insert(v);
+            }
+            Entry::Vacant(e) => {
+                e.insert(v);
+            }
+        }
+    }
+
+    ///Looks the key up once in each list, so the result can be
+    ///read, updated or filled without hashing again.
+    pub fn entry(&mut self, k: K) -> Entry<'_, K, V, S, T> {
+        Entry::new(self, k)
+    }
+
     pub fn get_mut<KR>(&mut self, kr: &KR) -> Option<&mut V>
     where
         K: Borrow<KR>,
         KR: Hash + Eq + ?Sized,
     {
-        if let Some(b) = self.main.get_mut(kr) {
-            Some(b)
-        } else {
-            self.grow.get_mut(kr)
-        }
+        let s = self.find(kr)?;
+        Some(&mut self.slot_mut(s).1)
     }
 
-    pub fn get<KR>(&mut self, kr: &KR) -> Option<&V>
+    pub fn get<KR>(&self, kr: &KR) -> Option<&V>
     where
         K: Borrow<KR>,
         KR: Hash + Eq + ?Sized,
     {
-        self.main.get(kr).or_else(|| self.grow.get(kr))
+        let s = self.find(kr)?;
+        Some(&self.slot(s).1)
+    }
+
+    pub fn remove<KR>(&mut self, kr: &KR) -> Option<V>
+    where
+        K: Borrow<KR>,
+        KR: Hash + Eq + ?Sized,
+    {
+        self.remove_entry(kr).map(|(_, v)| v)
+    }
+
+    pub fn remove_entry<KR>(&mut self, kr: &KR) -> Option<(K, V)>
+    where
+        K: Borrow<KR>,
+        KR: Hash + Eq + ?Sized,
+    {
+        let h = self.hash(kr);
+        self.main.remove(h, kr).or_else(|| self.grow.remove(h, kr))
+    }
+
+    ///Keeps only the entries for which f returns true.
+    ///Entries are visited in both lists, so this is safe mid-migration.
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.main.retain(&mut f);
+        self.grow.retain(&mut f);
+    }
+
+    ///Removes every entry but keeps the allocated buckets.
+    ///Any resize still waiting to start is dropped.
+    pub fn clear(&mut self) {
+        self.main.clear();
+        self.grow.clear();
+        self.resize_to = 0;
+        if self.n_moved > 0 {
+            //grow already has the target bucket count, so finish the swap early
+            std::mem::swap(&mut self.main, &mut self.grow);
+            self.n_moved = 0;
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V, T> {
+        Iter::new(&self.main, &self.grow, self.len())
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V, T> {
+        let left = self.len();
+        IterMut::new(&mut self.main, &mut self.grow, left)
+    }
+
+    pub fn keys(&self) -> Keys<'_, K, V, T> {
+        Keys(self.iter())
+    }
+
+    pub fn values(&self) -> Values<'_, K, V, T> {
+        Values(self.iter())
+    }
+
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V, T> {
+        ValuesMut(self.iter_mut())
+    }
+
+    ///Empties the map, yielding the entries.
+    ///The buckets stay allocated as with clear.
+    pub fn drain(&mut self) -> Drain<'_, K, V, T> {
+        let left = self.len();
+        self.resize_to = 0;
+        if self.n_moved > 0 {
+            std::mem::swap(&mut self.main, &mut self.grow);
+            self.n_moved = 0;
+        }
+        Drain::new(&mut self.main, &mut self.grow, left)
     }
 
     pub fn len(&self) -> usize {
@@ -66,35 +302,233 @@ where
         self.main.len() == 0 && self.grow.len() == 0
     }
 
+    fn find<KR>(&self, kr: &KR) -> Option<Slot>
+    where
+        K: Borrow<KR>,
+        KR: Hash + Eq + ?Sized,
+    {
+        let h = self.hash(kr);
+        match self.main.find(h, kr) {
+            Some(p) => Some(Slot::new(Side::Main, p)),
+            None => Some(Slot::new(Side::Grow, self.grow.find(h, kr)?)),
+        }
+    }
+
+    fn slot(&self, s: Slot) -> &(K, V) {
+        match s.side {
+            Side::Main => self.main.at(s.pos),
+            Side::Grow => self.grow.at(s.pos),
+        }
+    }
+
+    fn slot_mut(&mut self, s: Slot) -> &mut (K, V) {
+        match s.side {
+            Side::Main => self.main.at_mut(s.pos),
+            Side::Grow => self.grow.at_mut(s.pos),
+        }
+    }
+
+    ///Called before adding a new entry that hashes to h.
+    ///Moves a bucket if migrating, or starts growing if main is crowded.
+    fn step_migration(&mut self, h: u64) {
+        //Shrinking has more buckets to move than the new size has room for inserts,
+        //so it moves several per insert to finish before grow fills up.
+        let mut steps = (self.main.b_len() / self.grow.b_len()).max(1);
+        //A table with a hard limit can't run over, and slows sharply close to it,
+        //so it paces the moves to finish before grow reaches its capacity.
+        if self.n_moved > 0 && self.grow.limit().is_some() {
+            let room =
+                T::capacity_of(self.grow.b_len(), self.max_bucket).saturating_sub(self.len());
+            steps = steps.max((self.main.b_len() - self.n_moved).div_ceil(room.max(1)));
+        }
+        for _ in 0..steps {
+            if self.n_moved == 0 {
+                break;
+            }
+            self.move_bucket();
+        }
+        if self.n_moved == 0 && self.main.crowded(h, self.max_bucket) {
+            self.move_bucket();
+        }
+    }
+
+    ///Moves one bucket from main to grow
     fn move_bucket(&mut self) {
         if self.n_moved == 0 {
-            self.grow.set_buckets(self.main.b_len() * 2)
+            //a queued shrink may have been passed by inserts while it waited
+            let n = match std::mem::take(&mut self.resize_to) {
+                0 => self.main.b_len() * 2,
+                n => n.max(self.buckets_for(self.len())),
+            };
+            self.grow.set_buckets(n);
         }
 
-        if let Some(b) = self.main.bucket(self.n_moved) {
+        if let Some(b) = self.main.take_bucket(self.n_moved) {
             for (k, v) in b {
-                self.grow.push(k, v);
+                self.grow.push(self.hash(&k), k, v);
             }
             self.n_moved += 1;
         } else {
             std::mem::swap(&mut self.main, &mut self.grow);
             self.n_moved = 0;
+            //a reserve or shrink came in while this migration was running
+            if self.resize_to > 0 {
+                self.move_bucket();
+            }
         }
     }
 }
 
-impl<K, V> Default for HMap<K, V>
+///Guarded against floods as HMap::new is, if S can reseed
+impl<K, V, S, T> Default for HMap<K, V, S, T>
 where
     K: Hash + Eq,
+    S: MapHasher,
+    T: Table<K, V>,
 {
     fn default() -> Self {
-        Self::new()
+        Self::with_default_hasher(0)
+    }
+}
+
+///Prints as a map, `{k: v, ...}`, in iteration order
+impl<K, V, S, T> fmt::Debug for HMap<K, V, S, T>
+where
+    K: Hash + Eq + fmt::Debug,
+    V: fmt::Debug,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+///Equal when both hold the same entries, whatever their order or bucket count
+impl<K, V, S, T> PartialEq for HMap<K, V, S, T>
+where
+    K: Hash + Eq,
+    V: PartialEq,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl<K, V, S, T> Eq for HMap<K, V, S, T>
+where
+    K: Hash + Eq,
+    V: Eq,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+}
+
+///Panics if the key is not present
+impl<K, KR, V, S, T> Index<&KR> for HMap<K, V, S, T>
+where
+    K: Hash + Eq + Borrow<KR>,
+    KR: Hash + Eq + ?Sized,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    type Output = V;
+
+    fn index(&self, kr: &KR) -> &V {
+        self.get(kr).expect("key not found in HMap")
+    }
+}
+
+impl<K, V, S, T> Extend<(K, V)> for HMap<K, V, S, T>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<'a, K, V, S, T> Extend<(&'a K, &'a V)> for HMap<K, V, S, T>
+where
+    K: Hash + Eq + Copy,
+    V: Copy,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    fn extend<I: IntoIterator<Item = (&'a K, &'a V)>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().map(|(k, v)| (*k, *v)));
+    }
+}
+
+impl<K, V, S, T> FromIterator<(K, V)> for HMap<K, V, S, T>
+where
+    K: Hash + Eq,
+    S: MapHasher,
+    T: Table<K, V>,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut res = Self::default();
+        res.extend(iter);
+        res
+    }
+}
+
+impl<K, V, S, T> IntoIterator for HMap<K, V, S, T>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let left = self.len();
+        IntoIter::new(self.main, self.grow, left)
+    }
+}
+
+impl<'a, K, V, S, T> IntoIterator for &'a HMap<K, V, S, T>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K, V, S, T> IntoIterator for &'a mut HMap<K, V, S, T>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasherDefault, Hasher};
     #[test]
     fn test_get_right_values() {
         let mut hm = HMap::new();
@@ -130,6 +564,14 @@ mod tests {
         assert_eq!(hm.len(), 10000);
         assert_eq!(hm.get(&6000), Some(&6250));
 
+        let mut seen = vec![false; 10000];
+        for (k, v) in &hm {
+            assert_eq!(*v, k + 250);
+            assert!(!seen[*k as usize], "{} seen twice", k);
+            seen[*k as usize] = true;
+        }
+        assert!(seen.iter().all(|s| *s));
+
         for (n, x) in hm.main.buckets.iter().enumerate() {
             assert!(x.len() < 10, "main bucket too big {}:{}", n, x.len());
         }
@@ -138,4 +580,314 @@ mod tests {
             assert!(x.len() < 10, "grow bucket too big {}:{}", n, x.len());
         }
     }
+
+    #[test]
+    fn test_remove() {
+        let mut hm = HMap::new();
+        hm.insert("james", 18);
+        hm.insert("dave", 45);
+        hm.insert("andy", 23);
+
+        assert_eq!(hm.remove("dave"), Some(45));
+        assert_eq!(hm.remove("dave"), None);
+        assert_eq!(hm.remove_entry("andy"), Some(("andy", 23)));
+        assert_eq!(hm.get("dave"), None);
+        assert_eq!(hm.get("james"), Some(&18));
+        assert_eq!(hm.len(), 1);
+    }
+
+    #[test]
+    fn test_remove_during_migration() {
+        let mut hm = HMap::new();
+        let mut x = 0;
+        //keep inserting until a migration is part way through
+        while hm.n_moved == 0 || hm.main.len() == 0 {
+            hm.insert(x, x * 2);
+            x += 1;
+        }
+
+        for y in (0..x).step_by(2) {
+            assert_eq!(hm.remove(&y), Some(y * 2), "missing {}", y);
+        }
+        assert_eq!(hm.len(), (x / 2) as usize);
+        for y in 0..x {
+            let expect = if y % 2 == 0 { None } else { Some(&(y * 2)) };
+            assert_eq!(hm.get(&y), expect);
+        }
+
+        for z in x..x + 1000 {
+            hm.insert(z, z * 2);
+        }
+        assert_eq!(hm.len(), (x / 2) as usize + 1000);
+    }
+
+    #[test]
+    fn test_entry() {
+        let mut hm: HMap<String, usize> = HMap::new();
+        let words = "the cat sat on the mat and the dog sat on the cat";
+        for w in words.split(' ') {
+            *hm.entry(w.to_string()).or_insert(0) += 1;
+        }
+        assert_eq!(hm.get("the"), Some(&4));
+        assert_eq!(hm.get("cat"), Some(&2));
+        assert_eq!(hm.get("dog"), Some(&1));
+        assert_eq!(hm.len(), 7);
+
+        hm.entry("dog".to_string()).and_modify(|v| *v += 10);
+        hm.entry("fish".to_string()).and_modify(|v| *v += 10);
+        assert_eq!(hm.get("dog"), Some(&11));
+        assert_eq!(hm.get("fish"), None);
+
+        assert_eq!(*hm.entry("fish".to_string()).or_default(), 0);
+        assert_eq!(hm.entry("fish".to_string()).key(), "fish");
+        match hm.entry("mat".to_string()) {
+            Entry::Occupied(e) => assert_eq!(e.remove_entry(), ("mat".to_string(), 1)),
+            Entry::Vacant(_) => panic!("mat should be present"),
+        }
+        assert_eq!(hm.get("mat"), None);
+        assert_eq!(hm.len(), 7);
+    }
+
+    #[test]
+    fn test_entry_through_migrations() {
+        let mut hm = HMap::new();
+        for x in 0..5000 {
+            let v = hm.entry(x).or_insert_with(|| x * 3);
+            assert_eq!(*v, x * 3);
+            *v += 1;
+        }
+        assert_eq!(hm.len(), 5000);
+        for x in 0..5000 {
+            assert_eq!(hm.get(&x), Some(&(x * 3 + 1)));
+        }
+    }
+
+    #[test]
+    fn test_iter_during_migration() {
+        let mut hm = HMap::new();
+        let mut x = 0;
+        while hm.n_moved == 0 || hm.main.len() == 0 {
+            hm.insert(x, x);
+            x += 1;
+        }
+
+        let mut keys: Vec<i32> = hm.keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, (0..x).collect::<Vec<_>>());
+        assert_eq!(hm.iter().len(), x as usize);
+        assert_eq!(hm.values().sum::<i32>(), (0..x).sum());
+
+        for v in hm.values_mut() {
+            *v *= 2;
+        }
+        for (k, v) in &mut hm {
+            *v += k;
+        }
+        for (k, v) in hm.iter() {
+            assert_eq!(*v, k * 3);
+        }
+
+        let mut owned: Vec<(i32, i32)> = hm.into_iter().collect();
+        owned.sort();
+        assert_eq!(owned, (0..x).map(|k| (k, k * 3)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_drain() {
+        let mut hm = HMap::new();
+        for x in 0..100 {
+            hm.insert(x, x);
+        }
+        let mut got: Vec<(i32, i32)> = hm.drain().collect();
+        got.sort();
+        assert_eq!(got.len(), 100);
+        assert_eq!(got[40], (40, 40));
+        assert!(hm.is_empty());
+        assert_eq!(hm.iter().count(), 0);
+
+        for x in 0..100 {
+            hm.insert(x, x);
+        }
+        //dropping part way through still empties the map
+        assert_eq!(hm.drain().take(3).count(), 3);
+        assert!(hm.is_empty());
+        assert_eq!(hm.iter().count(), 0);
+        hm.insert(1, 2);
+        assert_eq!(hm.get(&1), Some(&2));
+    }
+
+    ///Deliberately poor, to check HMap only relies on the BuildHasher
+    #[derive(Default)]
+    struct XorHash(u64);
+
+    impl Hasher for XorHash {
+        fn write(&mut self, dt: &[u8]) {
+            for d in dt {
+                self.0 = self.0.rotate_left(5) ^ *d as u64;
+            }
+        }
+
+        fn finish(&self) -> u64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_other_hashers() {
+        let mut hm: HMap<_, _, _> = HMap::with_hasher(RandomState::new());
+        let mut hx: HMap<_, _, BuildHasherDefault<XorHash>> = HMap::default();
+        let mut hc: HMap<_, _, _> = HMap::with_capacity_and_hasher(3000, RandomMHash::with_seed(4));
+        for x in 0..3000 {
+            hm.insert(x, x + 1);
+            hx.insert(x, x + 2);
+            hc.insert(x, x + 3);
+        }
+        for x in 0..3000 {
+            assert_eq!(hm.get(&x), Some(&(x + 1)));
+            assert_eq!(hx.get(&x), Some(&(x + 2)));
+            assert_eq!(hc.get(&x), Some(&(x + 3)));
+        }
+        assert_eq!(hm.len(), 3000);
+        assert_eq!(hx.len(), 3000);
+        assert_eq!(hc.len(), 3000);
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let mut hm = HMap::with_capacity(1000);
+        let start = hm.main.b_len();
+        assert!(hm.capacity() >= 1000);
+        for x in 0..1000 {
+            hm.insert(x, x);
+        }
+        //spread over 1000 buckets there should be no long chains
+        assert!(hm.main.b_len() <= start * 2);
+
+        let mut hs = HMap::new();
+        hs.set_max_bucket(16);
+        assert_eq!(hs.max_bucket(), 16);
+        hs.reserve(1000);
+        assert!(hs.capacity() >= 1000);
+        for x in 0..1000 {
+            hs.insert(x, x);
+        }
+        assert!(hs.target_buckets() < hm.target_buckets());
+        for x in 0..1000 {
+            assert_eq!(hs.get(&x), Some(&x));
+        }
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut hm = HMap::new();
+        hm.insert(-1, -1);
+        hm.reserve(10000);
+        assert!(hm.capacity() >= 10001);
+        for x in 0..10000 {
+            hm.insert(x, x);
+            //reserving again part way through should be a no-op
+            if x == 5 {
+                hm.reserve(5000);
+            }
+        }
+        assert_eq!(hm.len(), 10001);
+        for x in -1..10000 {
+            assert_eq!(hm.get(&x), Some(&x));
+        }
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let mut hm = HMap::new();
+        for x in 0..10000 {
+            hm.insert(x, x);
+        }
+        //a shrink would wait for a growth that is still running
+        let mut x = -1;
+        while hm.n_moved > 0 {
+            hm.insert(x, x);
+            x -= 1;
+        }
+        hm.retain(|k, _| *k >= 0);
+        let big = hm.target_buckets();
+        hm.retain(|k, _| k % 100 == 0);
+        hm.shrink_to_fit();
+        let small = hm.target_buckets();
+        assert!(small < big / 10);
+
+        //the move is spread over inserts, finishing in about one per new bucket
+        let mut x = 10000;
+        while hm.main.b_len() != small {
+            hm.insert(x, x);
+            x += 1;
+            assert!(x - 10000 <= small as i32 + 1, "shrink never finished");
+        }
+        assert_eq!(hm.len(), 100 + (x - 10000) as usize);
+        for y in (0..10000).step_by(100).chain(10000..x) {
+            assert_eq!(hm.get(&y), Some(&y));
+        }
+        assert_eq!(hm.get(&1), None);
+    }
+
+    #[test]
+    fn test_retain_and_clear() {
+        let mut hm = HMap::new();
+        for x in 0..1000 {
+            hm.insert(x, x);
+        }
+
+        hm.retain(|k, v| {
+            *v += 1;
+            k % 3 == 0
+        });
+        assert_eq!(hm.len(), 334);
+        assert_eq!(hm.get(&999), Some(&1000));
+        assert_eq!(hm.get(&998), None);
+
+        hm.clear();
+        assert!(hm.is_empty());
+        assert_eq!(hm.get(&999), None);
+
+        hm.insert(5, 6);
+        assert_eq!(hm.get(&5), Some(&6));
+        assert_eq!(hm.len(), 1);
+    }
+
+    #[test]
+    fn test_std_traits() {
+        let hm: HMap<i32, i32> = (0..1000).map(|x| (x, x * 3)).collect();
+        assert_eq!(hm.len(), 1000);
+        assert_eq!(hm[&10], 30);
+        //get only needs a shared borrow
+        let r = &hm;
+        assert_eq!(r.get(&999), Some(&2997));
+
+        //same entries in a different order and bucket count are still equal
+        let mut other: HMap<i32, i32> = HMap::with_capacity(5000);
+        other.extend((0..1000).rev().map(|x| (x, x * 3)));
+        assert_eq!(hm, other);
+        other.extend([(&1000, &0)]);
+        assert_ne!(hm, other);
+
+        let mut cl = hm.clone();
+        assert_eq!(cl, hm);
+        *cl.get_mut(&4).unwrap() = 0;
+        assert_ne!(cl, hm);
+        assert_eq!(hm[&4], 12);
+
+        let mut small = HMap::new();
+        assert_eq!(format!("{:?}", small), "{}");
+        small.insert("a", 1);
+        assert_eq!(format!("{:?}", small), r#"{"a": 1}"#);
+        small.insert("b", 2);
+        let f = format!("{:?}", small);
+        assert!(f == r#"{"a": 1, "b": 2}"# || f == r#"{"b": 2, "a": 1}"#);
+    }
+
+    #[test]
+    #[should_panic(expected = "key not found")]
+    fn test_index_missing() {
+        let hm: OpenHMap<&str, i32> = [("a", 1)].into_iter().collect();
+        let _ = hm["b"];
+    }
 }
@@ -0,0 +1,360 @@
+use std::{borrow::Borrow, hash::Hash, iter::FilterMap, slice, vec};
+
+use super::table::{add_count, Pos, Table};
+
+#[derive(Debug, Clone)]
+pub struct Cell<K, V> {
+    h: u64,
+    kv: (K, V),
+}
+
+type Slots<K, V> = Vec<Option<Cell<K, V>>>;
+type IterFn<'a, K, V> = fn(&'a Option<Cell<K, V>>) -> Option<&'a (K, V)>;
+type IterMutFn<'a, K, V> = fn(&'a mut Option<Cell<K, V>>) -> Option<&'a mut (K, V)>;
+type IntoIterFn<K, V> = fn(Option<Cell<K, V>>) -> Option<(K, V)>;
+type DrainFn<'a, K, V> = fn(&'a mut Option<Cell<K, V>>) -> Option<(K, V)>;
+
+///Open addressing with Robin Hood linear probing.
+///Entries sit directly in one Vec, so a lookup is usually a single cache line
+///rather than a hop to a separate bucket allocation.
+///
+///The hash is kept with each entry, so probing can stop early
+///and a bucket (all entries whose home is one slot) can be found for migration.
+#[derive(Debug, Clone)]
+pub struct OpenTable<K, V> {
+    len: usize,
+    slots: Slots<K, V>,
+}
+
+///Picks a slot for h out of cap.
+///Linear probing turns any pattern in the low bits into long runs,
+///so h is spread over every bit first, then the high bits choose the slot.
+fn home_of(h: u64, cap: usize) -> usize {
+    let spread = h.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    ((spread as u128 * cap as u128) >> 64) as usize
+}
+
+///How far slot i is from the home of h, wrapping past the end
+fn dist_in(i: usize, h: u64, cap: usize) -> usize {
+    let home = home_of(h, cap);
+    if i >= home {
+        i - home
+    } else {
+        i + cap - home
+    }
+}
+
+fn empty_slots<K, V>(n: usize) -> Slots<K, V> {
+    (0..n.max(1)).map(|_| None).collect()
+}
+
+impl<K, V> OpenTable<K, V> {
+    fn home(&self, h: u64) -> usize {
+        home_of(h, self.slots.len())
+    }
+
+    ///How far slot i is from the home of hash h
+    fn dist(&self, i: usize, h: u64) -> usize {
+        dist_in(i, h, self.slots.len())
+    }
+
+    fn next(&self, i: usize) -> usize {
+        if i + 1 == self.slots.len() {
+            0
+        } else {
+            i + 1
+        }
+    }
+
+    ///Pulls following entries back over a hole, so probes never stop short
+    fn shift_back(&mut self, mut hole: usize) {
+        loop {
+            let nx = self.next(hole);
+            match &self.slots[nx] {
+                Some(c) if self.dist(nx, c.h) > 0 => {
+                    self.slots[hole] = self.slots[nx].take();
+                    hole = nx;
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+impl<K, V> Table<K, V> for OpenTable<K, V> {
+    type Iter<'a>
+        = FilterMap<slice::Iter<'a, Option<Cell<K, V>>>, IterFn<'a, K, V>>
+    where
+        K: 'a,
+        V: 'a;
+    type IterMut<'a>
+        = FilterMap<slice::IterMut<'a, Option<Cell<K, V>>>, IterMutFn<'a, K, V>>
+    where
+        K: 'a,
+        V: 'a;
+    type IntoIter = FilterMap<vec::IntoIter<Option<Cell<K, V>>>, IntoIterFn<K, V>>;
+    type Drain<'a>
+        = FilterMap<slice::IterMut<'a, Option<Cell<K, V>>>, DrainFn<'a, K, V>>
+    where
+        K: 'a,
+        V: 'a;
+
+    fn new() -> Self {
+        OpenTable {
+            len: 0,
+            slots: empty_slots(1),
+        }
+    }
+
+    ///Kept below 4/5 full, with a slot spare so probes always end
+    fn buckets_for(n: usize, _max_bucket: usize) -> usize {
+        ((n + 1) * 5).div_ceil(4)
+    }
+
+    fn capacity_of(nb: usize, _max_bucket: usize) -> usize {
+        (nb * 4 / 5).saturating_sub(1)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn b_len(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn set_buckets(&mut self, n: usize) {
+        debug_assert_eq!(self.len, 0);
+        self.slots = empty_slots(n);
+    }
+
+    fn find<KB>(&self, h: u64, k: &KB) -> Option<Pos>
+    where
+        K: Borrow<KB>,
+        KB: Hash + Eq + ?Sized,
+    {
+        let mut i = self.home(h);
+        for d in 0..self.slots.len() {
+            let c = self.slots[i].as_ref()?;
+            //Robin Hood keeps a probe run in home order, so a richer entry means k is absent
+            if self.dist(i, c.h) < d {
+                return None;
+            }
+            if c.h == h && k == c.kv.0.borrow() {
+                return Some((i, 0));
+            }
+            i = self.next(i);
+        }
+        None
+    }
+
+    fn push(&mut self, h: u64, k: K, v: V) -> Pos {
+        debug_assert!(self.len + 1 < self.slots.len());
+        let cap = self.slots.len();
+        let mut cur = Cell { h, kv: (k, v) };
+        let mut i = self.home(h);
+        let mut d = 0;
+        let mut res = None;
+        loop {
+            if let Some(c) = &mut self.slots[i] {
+                //the poorer entry takes the slot, the richer one moves on
+                let cd = dist_in(i, c.h, cap);
+                if cd < d {
+                    std::mem::swap(c, &mut cur);
+                    res.get_or_insert((i, 0));
+                    d = cd;
+                }
+            } else {
+                self.slots[i] = Some(cur);
+                self.len += 1;
+                return res.unwrap_or((i, 0));
+            }
+            i = self.next(i);
+            d += 1;
+        }
+    }
+
+    fn at(&self, (i, _): Pos) -> &(K, V) {
+        &self.slots[i].as_ref().expect("empty slot").kv
+    }
+
+    fn at_mut(&mut self, (i, _): Pos) -> &mut (K, V) {
+        &mut self.slots[i].as_mut().expect("empty slot").kv
+    }
+
+    fn remove_at(&mut self, (i, _): Pos) -> (K, V) {
+        let c = self.slots[i].take().expect("empty slot");
+        self.len -= 1;
+        self.shift_back(i);
+        c.kv
+    }
+
+    fn crowded(&self, _h: u64, _max_bucket: usize) -> bool {
+        (self.len + 1) * 5 > self.slots.len() * 4
+    }
+
+    fn walk_len(&self, (i, _): Pos) -> usize {
+        let c = self.slots[i].as_ref().expect("empty slot");
+        self.dist(i, c.h) + 1
+    }
+
+    fn histogram(&self, hist: &mut Vec<usize>) {
+        for (i, s) in self.slots.iter().enumerate() {
+            add_count(hist, s.as_ref().map_or(0, |c| self.dist(i, c.h) + 1));
+        }
+    }
+
+    ///One slot is always left empty, so probes end
+    fn limit(&self) -> Option<usize> {
+        Some(self.slots.len() - 1)
+    }
+
+    ///Empties slots from the back, so each removal is the end of its run
+    ///and nothing needs shifting.  The first step also takes the run
+    ///that wraps round to the front, as it is pulled back into the last slot.
+    fn take_bucket(&mut self, n: usize) -> Option<Vec<(K, V)>> {
+        let cap = self.slots.len();
+        if n >= cap {
+            return None;
+        }
+        let i = cap - 1 - n;
+        let mut res = Vec::new();
+        while self.slots[i].is_some() {
+            res.push(self.remove_at((i, 0)));
+        }
+        Some(res)
+    }
+
+    fn retain<F>(&mut self, f: &mut F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        //Removing in place can shift an entry past the scan, so rebuild instead
+        let n = self.slots.len();
+        let old = std::mem::replace(&mut self.slots, empty_slots(n));
+        self.len = 0;
+        for mut c in old.into_iter().flatten() {
+            if f(&c.kv.0, &mut c.kv.1) {
+                let (k, v) = c.kv;
+                self.push(c.h, k, v);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        for s in &mut self.slots {
+            *s = None;
+        }
+        self.len = 0;
+    }
+
+    fn iter(&self) -> Self::Iter<'_> {
+        let f: IterFn<'_, K, V> = |s| s.as_ref().map(|c| &c.kv);
+        self.slots.iter().filter_map(f)
+    }
+
+    fn iter_mut(&mut self) -> Self::IterMut<'_> {
+        let f: IterMutFn<'_, K, V> = |s| s.as_mut().map(|c| &mut c.kv);
+        self.slots.iter_mut().filter_map(f)
+    }
+
+    fn into_iter(self) -> Self::IntoIter {
+        let f: IntoIterFn<K, V> = |s| s.map(|c| c.kv);
+        self.slots.into_iter().filter_map(f)
+    }
+
+    fn drain(&mut self) -> Self::Drain<'_> {
+        self.len = 0;
+        let f: DrainFn<'_, K, V> = |s| s.take().map(|c| c.kv);
+        self.slots.iter_mut().filter_map(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{OpenHMap, RandomMHash};
+    use super::*;
+
+    ///A hash whose home is slot n of 8
+    fn h(n: usize) -> u64 {
+        (0..).find(|h| home_of(*h, 8) == n).unwrap()
+    }
+
+    #[test]
+    fn test_take_bucket_wraps() {
+        let mut t = OpenTable::new();
+        t.set_buckets(8);
+        //homes of 6 and 7 spill round past the end into slots 0 and 1
+        for (n, k) in [(6, "a"), (6, "b"), (7, "c"), (7, "d"), (0, "e"), (2, "f")] {
+            t.push(h(n), k, n);
+        }
+        assert_eq!(t.find(h(7), "d"), Some((1, 0)));
+        assert_eq!(t.find(h(0), "e"), Some((2, 0)));
+
+        //the last slot takes the whole wrapped run with it
+        let mut first = t.take_bucket(0).unwrap();
+        first.sort();
+        assert_eq!(first, vec![("b", 6), ("c", 7), ("d", 7)]);
+        //e shifts back to its home, and is still found
+        assert_eq!(t.find(h(0), "e"), Some((0, 0)));
+        assert_eq!(t.find(h(2), "f"), Some((2, 0)));
+        assert_eq!(t.len(), 3);
+
+        assert_eq!(t.take_bucket(1).unwrap(), vec![("a", 6)]);
+        assert_eq!(t.take_bucket(2).unwrap(), vec![]);
+        assert_eq!(t.take_bucket(5).unwrap(), vec![("f", 2)]);
+        assert_eq!(t.take_bucket(7).unwrap(), vec![("e", 0)]);
+        assert!(t.is_empty());
+        assert_eq!(t.take_bucket(8), None);
+    }
+
+    #[test]
+    fn test_open_hmap() {
+        let mut hm: OpenHMap<u64, u64> = OpenHMap::default();
+        for x in 0..20000 {
+            hm.insert(x, x * 2);
+            //lookups must also work part way through every migration
+            if x % 97 == 0 {
+                for y in (0..x).step_by(13) {
+                    assert_eq!(hm.get(&y), Some(&(y * 2)));
+                }
+            }
+        }
+        assert_eq!(hm.len(), 20000);
+        for x in (0..20000).step_by(2) {
+            assert_eq!(hm.remove(&x), Some(x * 2));
+        }
+        assert_eq!(hm.len(), 10000);
+        assert_eq!(hm.iter().count(), 10000);
+        for x in 0..20000 {
+            let expect = if x % 2 == 0 { None } else { Some(&(x * 2)) };
+            assert_eq!(hm.get(&x), expect);
+        }
+        *hm.entry(5).or_insert(0) += 1;
+        *hm.entry(6).or_insert(0) += 1;
+        assert_eq!(hm.get(&5), Some(&11));
+        assert_eq!(hm.get(&6), Some(&1));
+    }
+
+    #[test]
+    fn test_open_shrink_keeps_room() {
+        let mut hm: OpenHMap<u64, u64> = OpenHMap::with_hasher(RandomMHash::new());
+        for x in 0..5000 {
+            hm.insert(x, x);
+        }
+        hm.retain(|k, _| *k < 10);
+        hm.shrink_to_fit();
+        //inserting hard while shrinking must never fill the smaller table
+        for x in 5000..10000 {
+            hm.insert(x, x);
+        }
+        assert_eq!(hm.len(), 5010);
+        for x in (0..10).chain(5000..10000) {
+            assert_eq!(hm.get(&x), Some(&x));
+        }
+        let mut all: Vec<u64> = hm.drain().map(|(k, _)| k).collect();
+        all.sort();
+        assert_eq!(all.len(), 5010);
+        assert!(hm.is_empty());
+    }
+}
@@ -0,0 +1,286 @@
+//! Statistical checks on how well a hasher spreads keys.
+//!
+//! Each measure comes out as a badness score where 0 is ideal,
+//! so tests can pin a hasher's current scores and fail if they get worse.
+
+use std::{
+    fmt,
+    hash::{BuildHasher, Hash},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::{RandomMHash, Reseed};
+
+///Bucket counts a BucketList commonly has.
+///Growth doubles from 1, while reserve and with_capacity give any count.
+pub const MODULI: [usize; 6] = [16, 64, 1024, 4096, 97, 1000];
+
+///How often each output bit flips when one input bit does.
+///The worst and mean of |2p - 1| over every pair of bits, ideal 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Avalanche {
+    pub worst: f64,
+    pub mean: f64,
+}
+
+///How correlated flips of two output bits are when one input bit flips.
+///The worst and mean |correlation| over every input bit and output pair, ideal 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Independence {
+    pub worst: f64,
+    pub mean: f64,
+}
+
+///Chi-square of bucket counts for one key set, divided by its degrees of freedom,
+///for each of MODULI in turn.  Around 1 is ideal, much higher means clumping.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution {
+    pub keys: &'static str,
+    pub ratios: Vec<f64>,
+}
+
+impl Distribution {
+    pub fn worst(&self) -> f64 {
+        self.ratios.iter().cloned().fold(0.0, f64::max)
+    }
+}
+
+///Everything measured for one hasher
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub name: &'static str,
+    pub avalanche: Avalanche,
+    pub independence: Independence,
+    pub distribution: Vec<Distribution>,
+}
+
+///Flips each of the 64 bits of trials random u64 keys
+pub fn avalanche<S: BuildHasher>(s: &S, trials: usize, seed: u64) -> Avalanche {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut flips = [[0usize; 64]; 64];
+    for _ in 0..trials {
+        let x: u64 = rng.gen();
+        let h = s.hash_one(x);
+        for (i, row) in flips.iter_mut().enumerate() {
+            let d = h ^ s.hash_one(x ^ (1 << i));
+            for (j, f) in row.iter_mut().enumerate() {
+                *f += ((d >> j) & 1) as usize;
+            }
+        }
+    }
+    let biases: Vec<f64> = flips
+        .iter()
+        .flatten()
+        .map(|&f| (2.0 * f as f64 / trials as f64 - 1.0).abs())
+        .collect();
+    Avalanche {
+        worst: biases.iter().cloned().fold(0.0, f64::max),
+        mean: biases.iter().sum::<f64>() / biases.len() as f64,
+    }
+}
+
+///Correlation between output bits j and k flipping, for each input bit flipped.
+///An output bit that never or always flips counts as fully correlated.
+pub fn independence<S: BuildHasher>(s: &S, trials: usize, seed: u64) -> Independence {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (mut worst, mut total, mut count) = (0.0f64, 0.0, 0usize);
+    let diffs: Vec<Vec<u64>> = (0..trials)
+        .map(|_| {
+            let x: u64 = rng.gen();
+            let h = s.hash_one(x);
+            (0..64).map(|i| h ^ s.hash_one(x ^ (1 << i))).collect()
+        })
+        .collect();
+    let n = trials as f64;
+    for i in 0..64 {
+        let mut single = [0usize; 64];
+        let mut both = vec![[0usize; 64]; 64];
+        for d in diffs.iter().map(|t| t[i]) {
+            for j in 0..64 {
+                if (d >> j) & 1 == 1 {
+                    single[j] += 1;
+                    for (k, b) in both[j].iter_mut().enumerate().skip(j + 1) {
+                        *b += ((d >> k) & 1) as usize;
+                    }
+                }
+            }
+        }
+        for j in 0..64 {
+            for k in j + 1..64 {
+                let (pj, pk) = (single[j] as f64 / n, single[k] as f64 / n);
+                let pjk = both[j][k] as f64 / n;
+                let den = (pj * (1.0 - pj) * pk * (1.0 - pk)).sqrt();
+                let c = if den == 0.0 {
+                    1.0
+                } else {
+                    ((pjk - pj * pk) / den).abs()
+                };
+                worst = worst.max(c);
+                total += c;
+                count += 1;
+            }
+        }
+    }
+    Independence {
+        worst,
+        mean: total / count as f64,
+    }
+}
+
+///Chi-square over `h % m` for each of MODULI, m * per keys at a time,
+///taking the hash of each key as a BucketList would.
+pub fn distribution<S, K, F>(s: &S, keys: &'static str, per: usize, key: F) -> Distribution
+where
+    S: BuildHasher,
+    K: Hash,
+    F: Fn(usize) -> K,
+{
+    let ratios = MODULI
+        .iter()
+        .map(|&m| {
+            let n = m * per;
+            let mut counts = vec![0usize; m];
+            for x in 0..n {
+                counts[(s.hash_one(key(x)) % m as u64) as usize] += 1;
+            }
+            let e = per as f64;
+            let chi: f64 = counts.iter().map(|&c| (c as f64 - e).powi(2) / e).sum();
+            chi / (m - 1) as f64
+        })
+        .collect();
+    Distribution { keys, ratios }
+}
+
+///Runs every measure over s with the sizes the report uses
+pub fn measure<S: BuildHasher>(name: &'static str, s: &S) -> Report {
+    let mut rng = StdRng::seed_from_u64(1);
+    let randoms: Vec<u64> = (0..MODULI.iter().max().unwrap() * 32)
+        .map(|_| rng.gen())
+        .collect();
+    Report {
+        name,
+        avalanche: avalanche(s, 2000, 2),
+        independence: independence(s, 400, 3),
+        distribution: vec![
+            distribution(s, "sequential", 32, |x| x as u64),
+            distribution(s, "random", 32, |x| randoms[x]),
+            distribution(s, "strings", 32, |x| format!("key{}", x)),
+            //keys that differ only in their high bits
+            distribution(s, "strided", 32, |x| (x as u64) << 40),
+        ],
+    }
+}
+
+///Reports for every hasher the crate ships, each with a fixed seed
+pub fn shipped() -> Vec<Report> {
+    let mut keyed = RandomMHash::with_seed(55);
+    keyed.reseed();
+    vec![
+        measure("MHash", &RandomMHash::with_seed(55)),
+        measure("RandomMHash reseeded (SipHash)", &keyed),
+    ]
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.name)?;
+        writeln!(
+            f,
+            "  avalanche bias       worst {:.3}  mean {:.3}",
+            self.avalanche.worst, self.avalanche.mean
+        )?;
+        writeln!(
+            f,
+            "  bit independence     worst {:.3}  mean {:.3}",
+            self.independence.worst, self.independence.mean
+        )?;
+        write!(f, "  chi-square / df      moduli")?;
+        for m in MODULI {
+            write!(f, " {:>6}", m)?;
+        }
+        writeln!(f)?;
+        for d in &self.distribution {
+            write!(f, "    {:<18} {:>6}", d.keys, "")?;
+            for r in &d.ratios {
+                write!(f, " {:>6.2}", r)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl Report {
+    ///Every way this report falls short of a hasher with random output.
+    ///Bounds sit about six standard deviations of sampling noise from ideal,
+    ///so a good hasher passes whatever its seed.
+    pub fn failures(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        let a = &self.avalanche;
+        if a.worst > 0.135 || a.mean > 0.025 {
+            res.push(format!("{}: avalanche {:?}", self.name, a));
+        }
+        let b = &self.independence;
+        if b.worst > 0.3 || b.mean > 0.05 {
+            res.push(format!("{}: bit independence {:?}", self.name, b));
+        }
+        for d in &self.distribution {
+            for (m, r) in MODULI.iter().zip(&d.ratios) {
+                let df = (m - 1) as f64;
+                if *r > 1.0 + 6.0 * (2.0 / df).sqrt() {
+                    res.push(format!(
+                        "{}: {} keys mod {} chi-square/df {:.2}",
+                        self.name, d.keys, m, r
+                    ));
+                }
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hash::{BuildHasherDefault, Hasher};
+
+    ///Hashes an integer to itself, like many quick integer hashers
+    #[derive(Default)]
+    struct Identity(u64);
+
+    impl Hasher for Identity {
+        fn write_u64(&mut self, n: u64) {
+            self.0 = n;
+        }
+
+        fn write(&mut self, dt: &[u8]) {
+            for b in dt {
+                self.0 = (self.0 << 8) | *b as u64;
+            }
+        }
+
+        fn finish(&self) -> u64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_shipped_hashers() {
+        for r in shipped() {
+            println!("{}", r);
+            assert_eq!(r.failures(), Vec::<String>::new());
+        }
+    }
+
+    #[test]
+    fn test_catches_weak_hasher() {
+        let r = measure("identity", &BuildHasherDefault::<Identity>::default());
+        let f = r.failures();
+        assert!(f.iter().any(|s| s.contains("avalanche")));
+        assert!(f.iter().any(|s| s.contains("bit independence")));
+        assert!(f.iter().any(|s| s.contains("strided keys mod 16 ")));
+        //sequential keys land evenly, so no false alarm there
+        assert!(!f.iter().any(|s| s.contains("sequential")));
+    }
+}
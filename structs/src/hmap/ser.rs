@@ -0,0 +1,164 @@
+use std::{
+    fmt,
+    hash::{BuildHasher, Hash},
+    marker::PhantomData,
+};
+
+use serde::{
+    de::{MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use super::{table::Table, HMap, MapHasher};
+
+///Most entries deserialize will make room for up front,
+///so a bad length can't ask for a huge allocation before any data arrives.
+const MAX_PRESIZE: usize = 1 << 20;
+
+///Written as a plain map of entries.
+///Neither the hasher's seed nor the main/grow split is part of the output,
+///though entries come out in iteration order, which follows the seed,
+///so equal maps may list them differently.  Serialize sorted() for the same bytes every time.
+impl<K, V, S, T> Serialize for HMap<K, V, S, T>
+where
+    K: Hash + Eq + Serialize,
+    V: Serialize,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    fn serialize<Z: Serializer>(&self, ser: Z) -> Result<Z::Ok, Z::Error> {
+        let mut m = ser.serialize_map(Some(self.len()))?;
+        for (k, v) in self {
+            m.serialize_entry(k, v)?;
+        }
+        m.end()
+    }
+}
+
+///A map's entries in key order, to serialize the same whatever its seed.
+///From HMap::sorted.
+pub struct Sorted<'a, K, V, S, T>(&'a HMap<K, V, S, T>);
+
+impl<K, V, S, T> HMap<K, V, S, T>
+where
+    K: Ord,
+{
+    ///The map, serializing with its entries in key order
+    pub fn sorted(&self) -> Sorted<'_, K, V, S, T> {
+        Sorted(self)
+    }
+}
+
+impl<K, V, S, T> Serialize for Sorted<'_, K, V, S, T>
+where
+    K: Hash + Eq + Ord + Serialize,
+    V: Serialize,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    fn serialize<Z: Serializer>(&self, ser: Z) -> Result<Z::Ok, Z::Error> {
+        let mut entries: Vec<(&K, &V)> = self.0.iter().collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        let mut m = ser.serialize_map(Some(entries.len()))?;
+        for (k, v) in entries {
+            m.serialize_entry(k, v)?;
+        }
+        m.end()
+    }
+}
+
+struct HMapVisitor<K, V, S, T>(PhantomData<HMap<K, V, S, T>>);
+
+impl<'de, K, V, S, T> Visitor<'de> for HMapVisitor<K, V, S, T>
+where
+    K: Hash + Eq + Deserialize<'de>,
+    V: Deserialize<'de>,
+    S: MapHasher,
+    T: Table<K, V>,
+{
+    type Value = HMap<K, V, S, T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut acc: A) -> Result<Self::Value, A::Error> {
+        let cap = acc.size_hint().unwrap_or(0).min(MAX_PRESIZE);
+        let mut res = HMap::with_default_hasher(cap);
+        while let Some((k, v)) = acc.next_entry()? {
+            res.insert(k, v);
+        }
+        Ok(res)
+    }
+}
+
+///Reads any map, sizing the buckets from its length where the format gives one.
+///A fresh hasher is made with S::default, so the seed differs from the original map's,
+///and the map guards against floods as HMap::new does if S can reseed.
+impl<'de, K, V, S, T> Deserialize<'de> for HMap<K, V, S, T>
+where
+    K: Hash + Eq + Deserialize<'de>,
+    V: Deserialize<'de>,
+    S: MapHasher,
+    T: Table<K, V>,
+{
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        de.deserialize_map(HMapVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{OpenHMap, RandomMHash};
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_round_trip() {
+        let mut hm = HMap::new();
+        //stop part way through a migration, so both lists hold entries
+        let mut x = 0;
+        while x < 1000 || hm.n_moved == 0 {
+            hm.insert(x, format!("v{}", x));
+            x += 1;
+        }
+        let dat = bincode::serialize(&hm).unwrap();
+        let back: HMap<i32, String> = bincode::deserialize(&dat).unwrap();
+        assert_eq!(back, hm);
+        assert!(back.capacity() >= hm.len());
+
+        let open: OpenHMap<i32, String> = bincode::deserialize(&dat).unwrap();
+        assert_eq!(open.len(), hm.len());
+        assert_eq!(open[&999], "v999");
+    }
+
+    #[test]
+    fn test_plain_map() {
+        let hm: HMap<String, u8> = (0..50).map(|x| (x.to_string(), x)).collect();
+        let dat = bincode::serialize(&hm).unwrap();
+        let std_map: HashMap<String, u8> = bincode::deserialize(&dat).unwrap();
+        assert_eq!(std_map.len(), 50);
+        assert_eq!(std_map["42"], 42);
+
+        let dat = bincode::serialize(&std_map).unwrap();
+        let back: HMap<String, u8> = bincode::deserialize(&dat).unwrap();
+        assert_eq!(back, hm);
+    }
+
+    #[test]
+    fn test_sorted_ignores_seed() {
+        let build = |seed| {
+            let mut hm = HMap::with_hasher(RandomMHash::with_seed(seed));
+            for x in 0..200 {
+                hm.insert(x, x * 2);
+            }
+            hm
+        };
+        let (a, b) = (build(1), build(2));
+        let dat = bincode::serialize(&a.sorted()).unwrap();
+        assert_eq!(dat, bincode::serialize(&b.sorted()).unwrap());
+        let back: HMap<i32, i32> = bincode::deserialize(&dat).unwrap();
+        assert_eq!(back, a);
+    }
+}
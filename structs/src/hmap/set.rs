@@ -0,0 +1,449 @@
+use std::{
+    borrow::Borrow,
+    fmt,
+    hash::{BuildHasher, Hash},
+    iter::Chain,
+};
+
+use super::{Entry, HMap, IntoIter, Keys, MapHasher, RandomMHash};
+
+///A set of keys, stored as an HMap with no values
+#[derive(Clone)]
+pub struct HSet<T, S = RandomMHash> {
+    map: HMap<T, (), S>,
+}
+
+pub struct SetIter<'a, T>(Keys<'a, T, ()>);
+
+pub struct SetIntoIter<T>(IntoIter<T, ()>);
+
+///Entries of a that are also in b
+pub struct Intersection<'a, T, S> {
+    iter: SetIter<'a, T>,
+    other: &'a HSet<T, S>,
+}
+
+///Entries of a that are not in b
+pub struct Difference<'a, T, S> {
+    iter: SetIter<'a, T>,
+    other: &'a HSet<T, S>,
+}
+
+///All of a, then whatever of b is not in a
+pub struct Union<'a, T, S>(Chain<SetIter<'a, T>, Difference<'a, T, S>>);
+
+pub struct SymmetricDifference<'a, T, S>(Chain<Difference<'a, T, S>, Difference<'a, T, S>>);
+
+impl<T> HSet<T>
+where
+    T: Hash + Eq,
+{
+    pub fn new() -> Self {
+        HSet { map: HMap::new() }
+    }
+
+    pub fn with_capacity(cap: usize) -> Self {
+        HSet {
+            map: HMap::with_capacity(cap),
+        }
+    }
+}
+
+impl<T, S> HSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    pub fn with_hasher(hasher: S) -> Self {
+        HSet {
+            map: HMap::with_hasher(hasher),
+        }
+    }
+
+    ///Returns false if t was already in the set, leaving the old one in place
+    pub fn insert(&mut self, t: T) -> bool {
+        match self.map.entry(t) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(e) => {
+                e.insert(());
+                true
+            }
+        }
+    }
+
+    pub fn contains<Q>(&self, q: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(q).is_some()
+    }
+
+    ///The stored value equal to q, if any
+    pub fn get<Q>(&self, q: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let s = self.map.find(q)?;
+        Some(&self.map.slot(s).0)
+    }
+
+    pub fn remove<Q>(&mut self, q: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove(q).is_some()
+    }
+
+    ///Removes and returns the stored value equal to q
+    pub fn take<Q>(&mut self, q: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove_entry(q).map(|(t, _)| t)
+    }
+
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.map.retain(|t, _| f(t));
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.map.reserve(additional);
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.map.shrink_to_fit();
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.map.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> SetIter<'_, T> {
+        SetIter(self.map.keys())
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.map.drain().map(|(t, _)| t)
+    }
+
+    pub fn intersection<'a>(&'a self, other: &'a Self) -> Intersection<'a, T, S> {
+        //walk the smaller set, checking the larger
+        let (a, b) = if self.len() <= other.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        Intersection {
+            iter: a.iter(),
+            other: b,
+        }
+    }
+
+    pub fn difference<'a>(&'a self, other: &'a Self) -> Difference<'a, T, S> {
+        Difference {
+            iter: self.iter(),
+            other,
+        }
+    }
+
+    pub fn union<'a>(&'a self, other: &'a Self) -> Union<'a, T, S> {
+        Union(self.iter().chain(other.difference(self)))
+    }
+
+    pub fn symmetric_difference<'a>(&'a self, other: &'a Self) -> SymmetricDifference<'a, T, S> {
+        SymmetricDifference(self.difference(other).chain(other.difference(self)))
+    }
+
+    pub fn is_disjoint(&self, other: &Self) -> bool {
+        self.intersection(other).next().is_none()
+    }
+
+    pub fn is_subset(&self, other: &Self) -> bool {
+        self.len() <= other.len() && self.iter().all(|t| other.contains(t))
+    }
+
+    pub fn is_superset(&self, other: &Self) -> bool {
+        other.is_subset(self)
+    }
+}
+
+impl<T, S> Default for HSet<T, S>
+where
+    T: Hash + Eq,
+    S: MapHasher,
+{
+    fn default() -> Self {
+        HSet {
+            map: HMap::default(),
+        }
+    }
+}
+
+///Prints as a set, `{a, b, ...}`, in iteration order
+impl<T, S> fmt::Debug for HSet<T, S>
+where
+    T: Hash + Eq + fmt::Debug,
+    S: BuildHasher,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<T, S> PartialEq for HSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.is_subset(other)
+    }
+}
+
+impl<T, S> Eq for HSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+}
+
+impl<T, S> Extend<T> for HSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.map.extend(iter.into_iter().map(|t| (t, ())));
+    }
+}
+
+impl<T, S> FromIterator<T> for HSet<T, S>
+where
+    T: Hash + Eq,
+    S: MapHasher,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut res = Self::default();
+        res.extend(iter);
+        res
+    }
+}
+
+impl<T, S> IntoIterator for HSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = T;
+    type IntoIter = SetIntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        SetIntoIter(self.map.into_iter())
+    }
+}
+
+impl<'a, T, S> IntoIterator for &'a HSet<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = &'a T;
+    type IntoIter = SetIter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T> Iterator for SetIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, T> ExactSizeIterator for SetIter<'a, T> {}
+
+impl<T> Iterator for SetIntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.0.next().map(|(t, _)| t)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<T> ExactSizeIterator for SetIntoIter<T> {}
+
+impl<'a, T, S> Iterator for Intersection<'a, T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let other = self.other;
+        self.iter.find(|t| other.contains(*t))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+impl<'a, T, S> Iterator for Difference<'a, T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let other = self.other;
+        self.iter.find(|t| !other.contains(*t))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.iter.size_hint().1)
+    }
+}
+
+impl<'a, T, S> Iterator for Union<'a, T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, T, S> Iterator for SymmetricDifference<'a, T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted<'a, I: Iterator<Item = &'a i32>>(it: I) -> Vec<i32> {
+        let mut v: Vec<i32> = it.cloned().collect();
+        v.sort();
+        v
+    }
+
+    #[test]
+    fn test_insert_remove() {
+        let mut s = HSet::new();
+        assert!(s.insert("a".to_string()));
+        assert!(!s.insert("a".to_string()));
+        assert!(s.insert("b".to_string()));
+        assert!(s.contains("a"));
+        assert_eq!(s.get("b"), Some(&"b".to_string()));
+        assert_eq!(s.len(), 2);
+        assert!(s.remove("a"));
+        assert!(!s.remove("a"));
+        assert_eq!(s.take("b"), Some("b".to_string()));
+        assert!(s.is_empty());
+
+        let mut big: HSet<i32> = (0..1000).collect();
+        big.retain(|x| x % 10 == 0);
+        assert_eq!(big.len(), 100);
+        assert_eq!(format!("{:?}", HSet::<i32>::from_iter([7])), "{7}");
+    }
+
+    #[test]
+    fn test_set_algebra() {
+        let a: HSet<i32> = (0..10).collect();
+        let b: HSet<i32> = (5..15).collect();
+        assert_eq!(sorted(a.union(&b)), (0..15).collect::<Vec<_>>());
+        assert_eq!(sorted(a.intersection(&b)), (5..10).collect::<Vec<_>>());
+        assert_eq!(sorted(b.intersection(&a)), (5..10).collect::<Vec<_>>());
+        assert_eq!(sorted(a.difference(&b)), (0..5).collect::<Vec<_>>());
+        assert_eq!(sorted(b.difference(&a)), (10..15).collect::<Vec<_>>());
+        assert_eq!(
+            sorted(a.symmetric_difference(&b)),
+            (0..5).chain(10..15).collect::<Vec<_>>()
+        );
+
+        let c: HSet<i32> = (2..4).collect();
+        assert!(c.is_subset(&a));
+        assert!(a.is_superset(&c));
+        assert!(!a.is_subset(&b));
+        assert!(c.is_disjoint(&b));
+        assert!(!a.is_disjoint(&b));
+        //lazy, so only as much is checked as is taken
+        assert_eq!(a.union(&b).take(3).count(), 3);
+    }
+
+    #[test]
+    fn test_eq_and_iter() {
+        let a: HSet<i32> = (0..100).collect();
+        let mut b = HSet::with_capacity(500);
+        b.extend((0..100).rev());
+        assert_eq!(a, b);
+        b.insert(100);
+        assert_ne!(a, b);
+        assert_eq!(sorted(a.iter()), (0..100).collect::<Vec<_>>());
+        let mut owned: Vec<i32> = b.clone().into_iter().collect();
+        owned.sort();
+        assert_eq!(owned, (0..101).collect::<Vec<_>>());
+        assert_eq!(b.drain().count(), 101);
+        assert!(b.is_empty());
+    }
+
+    #[test]
+    fn test_collected_set_is_guarded() {
+        let keys = super::super::stats::colliding(256);
+        let s: HSet<[u8; 25]> = keys.iter().cloned().collect();
+        assert_eq!(s.map.stats().reseeds, 1);
+        assert!(keys.iter().all(|k| s.contains(k)));
+        assert!(HSet::<[u8; 25]>::default().map.reseed.is_some());
+    }
+}
@@ -0,0 +1,290 @@
+use std::hash::{BuildHasher, Hash};
+
+use super::{
+    entry::{Side, Slot},
+    hasher::Reseed,
+    table::Table,
+    HMap,
+};
+
+///Longest walk to an entry before the map reseeds, unless max_bucket is larger.
+///Honest keys should never get near it, so a chain this long means they were
+///chosen to collide.
+pub(super) const FLOOD_LIMIT: usize = 64;
+
+///A snapshot of how an HMap's entries are spread, from HMap::stats
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HMapStats {
+    pub len: usize,
+    ///Buckets in main
+    pub buckets: usize,
+    ///Buckets in grow, 0 unless migrating
+    pub grow_buckets: usize,
+    ///Buckets of main already moved to grow
+    pub n_moved: usize,
+    ///The longest walk a lookup can take, from either list
+    pub max_chain: usize,
+    ///histogram[n] counts buckets whose lookups walk n entries, across both lists.
+    ///See Table::histogram for what that means for an OpenTable.
+    pub histogram: Vec<usize>,
+    ///How many times a flood of collisions has made the map rehash
+    pub reseeds: usize,
+}
+
+impl<K, V, S, T> HMap<K, V, S, T>
+where
+    K: Hash + Eq,
+    S: BuildHasher,
+    T: Table<K, V>,
+{
+    ///Walks every bucket, so costs as much as iterating the map
+    pub fn stats(&self) -> HMapStats {
+        let mut histogram = Vec::new();
+        self.main.histogram(&mut histogram);
+        let grow_buckets = if self.n_moved > 0 {
+            self.grow.histogram(&mut histogram);
+            self.grow.b_len()
+        } else {
+            0
+        };
+        HMapStats {
+            len: self.len(),
+            buckets: self.main.b_len(),
+            grow_buckets,
+            n_moved: self.n_moved,
+            max_chain: histogram.len().saturating_sub(1),
+            histogram,
+            reseeds: self.reseeds,
+        }
+    }
+
+    ///Called with the slot of an entry just added.
+    ///If reaching it walks too far, reseeds the hasher and rehashes everything,
+    ///returning where the entry is now.
+    pub(super) fn check_flood(&mut self, s: Slot) -> Slot {
+        let Some(reseed) = self.reseed else {
+            return s;
+        };
+        let walk = match s.side {
+            Side::Main => self.main.walk_len(s.pos),
+            Side::Grow => self.grow.walk_len(s.pos),
+        };
+        if walk <= self.flood_limit.max(self.max_bucket * 4) {
+            return s;
+        }
+        reseed(&mut self.hasher);
+        self.reseeds += 1;
+        //a hasher that can't spread these keys would otherwise rehash on every insert
+        self.flood_limit *= 2;
+        self.rehash(s)
+    }
+
+    ///Moves every entry into a fresh main list under the current hasher,
+    ///finishing any migration.  The entry at keep goes in last,
+    ///so no other push can move it, and its new slot is returned.
+    fn rehash(&mut self, keep: Slot) -> Slot {
+        let kept = match keep.side {
+            Side::Main => self.main.remove_at(keep.pos),
+            Side::Grow => self.grow.remove_at(keep.pos),
+        };
+        let mut all: Vec<(K, V)> = self.main.drain().chain(self.grow.drain()).collect();
+        all.push(kept);
+
+        let n = self.target_buckets().max(self.buckets_for(all.len()));
+        self.resize_to = 0;
+        self.n_moved = 0;
+        self.grow.set_buckets(1);
+        self.main.set_buckets(n);
+        let mut pos = None;
+        for (k, v) in all {
+            pos = Some(self.main.push(self.hash(&k), k, v));
+        }
+        Slot::new(Side::Main, pos.expect("kept entry was pushed"))
+    }
+}
+
+impl<K, V, S, T> HMap<K, V, S, T>
+where
+    K: Hash + Eq,
+    S: Reseed,
+    T: Table<K, V>,
+{
+    ///Sets how far a lookup may walk to a newly added entry
+    ///before the map decides it is being flooded, then reseeds and rehashes.
+    ///The limit is never taken as less than 4 * max_bucket,
+    ///and doubles after each reseed.  0 turns the check off.
+    ///
+    ///Maps made by new, with_capacity, default, collect or deserialize start with this on.
+    pub fn set_flood_limit(&mut self, limit: usize) {
+        self.flood_limit = limit;
+        self.reseed = match limit {
+            0 => None,
+            _ => Some(S::reseed),
+        };
+    }
+}
+
+///n keys, up to 256, that MHash sends to the same hash whatever the seed.
+///The leading 0 makes the seed's last byte drop out,
+///then each key is 8 blocks, each [17, 71, 0] or [43, 32, 0],
+///which take MHash from any state after a 0 to the same state.
+#[cfg(test)]
+pub(super) fn colliding(n: usize) -> Vec<[u8; 25]> {
+    (0..n)
+        .map(|i| {
+            let mut k = [0u8; 25];
+            for b in 0..8 {
+                let block = if i >> b & 1 == 0 { [17, 71] } else { [43, 32] };
+                k[1 + b * 3..3 + b * 3].copy_from_slice(&block);
+            }
+            k
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::OpenHMap;
+    use super::*;
+    use std::{
+        collections::hash_map::{DefaultHasher, RandomState},
+        hash::Hasher,
+    };
+
+    ///Sends every key to the same hash until reseeded
+    #[derive(Default)]
+    struct Flooded {
+        keyed: Option<RandomState>,
+    }
+
+    struct FloodHasher(Option<DefaultHasher>);
+
+    impl Hasher for FloodHasher {
+        fn write(&mut self, dt: &[u8]) {
+            if let Some(h) = &mut self.0 {
+                h.write(dt);
+            }
+        }
+
+        fn finish(&self) -> u64 {
+            self.0.as_ref().map_or(7, |h| h.finish())
+        }
+    }
+
+    impl BuildHasher for Flooded {
+        type Hasher = FloodHasher;
+
+        fn build_hasher(&self) -> FloodHasher {
+            FloodHasher(self.keyed.as_ref().map(|k| k.build_hasher()))
+        }
+    }
+
+    impl Reseed for Flooded {
+        fn reseed(&mut self) {
+            self.keyed = Some(RandomState::new());
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut hm = HMap::new();
+        let mut x = 0;
+        while x < 1000 || hm.n_moved == 0 {
+            hm.insert(x, x);
+            x += 1;
+        }
+        let st = hm.stats();
+        assert_eq!(st.len, x);
+        assert_eq!(st.n_moved, hm.n_moved);
+        assert_eq!(st.grow_buckets, st.buckets * 2);
+        assert_eq!(
+            st.histogram.iter().sum::<usize>(),
+            st.buckets + st.grow_buckets
+        );
+        let entries: usize = st.histogram.iter().enumerate().map(|(n, c)| n * c).sum();
+        assert_eq!(entries, x);
+        assert_eq!(st.max_chain + 1, st.histogram.len());
+        assert_eq!(st.reseeds, 0);
+
+        let open: OpenHMap<i32, i32> = (0..1000).map(|x| (x, x)).collect();
+        let st = open.stats();
+        //empty slots count at 0, entries at one more than their distance from home
+        let counted: usize = st.histogram[1..].iter().sum();
+        assert_eq!(counted, 1000);
+    }
+
+    #[test]
+    fn test_flood_reseeds() {
+        let mut hm: HMap<i32, i32, Flooded> = HMap::with_hasher(Flooded::default());
+        hm.set_flood_limit(16);
+        for x in 0..1000 {
+            hm.insert(x, x * 2);
+        }
+        let st = hm.stats();
+        assert_eq!(st.reseeds, 1);
+        assert!(st.max_chain < 16);
+        for x in 0..1000 {
+            assert_eq!(hm.get(&x), Some(&(x * 2)));
+        }
+
+        //the same flood with the check off piles the keys into one or two buckets
+        let mut hm: HMap<i32, i32, Flooded> = HMap::with_hasher(Flooded::default());
+        for x in 0..200 {
+            hm.insert(x, x);
+        }
+        let st = hm.stats();
+        assert_eq!(st.reseeds, 0);
+        assert!(st.max_chain >= 100);
+    }
+
+    #[test]
+    fn test_flood_open_table() {
+        let mut hm: OpenHMap<i32, i32, Flooded> = HMap::with_hasher(Flooded::default());
+        hm.set_flood_limit(16);
+        for x in 0..1000 {
+            *hm.entry(x).or_insert(0) += x;
+        }
+        assert_eq!(hm.stats().reseeds, 1);
+        assert_eq!(hm.len(), 1000);
+        assert!(hm.iter().all(|(k, v)| k == v));
+    }
+
+    #[test]
+    fn test_default_map_is_guarded() {
+        let keys = colliding(256);
+        let mut hm: HMap<[u8; 25], usize> = HMap::new();
+        for (i, k) in keys[..60].iter().enumerate() {
+            hm.insert(*k, i);
+        }
+        //one chain, or two while a grow is moving it, still under the limit
+        let st = hm.stats();
+        assert!(st.max_chain >= 30);
+        assert_eq!(st.reseeds, 0);
+        assert!(!hm.hasher().is_keyed());
+        for (i, k) in keys.iter().enumerate().skip(60) {
+            hm.insert(*k, i);
+        }
+        let st = hm.stats();
+        assert_eq!(st.reseeds, 1);
+        assert!(hm.hasher().is_keyed());
+        assert!(st.max_chain < 16);
+        assert!(keys.iter().enumerate().all(|(i, k)| hm[k] == i));
+    }
+
+    #[test]
+    fn test_collected_map_is_guarded() {
+        let keys = colliding(256);
+        let hm: HMap<[u8; 25], usize> = keys.iter().map(|k| (*k, 0)).collect();
+        assert_eq!(hm.stats().reseeds, 1);
+        assert!(hm.stats().max_chain < 16);
+
+        let mut hm: HMap<[u8; 25], usize> = HMap::default();
+        hm.extend(keys.iter().map(|k| (*k, 0)));
+        assert!(hm.hasher().is_keyed());
+
+        let dat = bincode::serialize(&hm).unwrap();
+        let back: OpenHMap<[u8; 25], usize> = bincode::deserialize(&dat).unwrap();
+        assert_eq!(back.stats().reseeds, 1);
+        assert_eq!(back.len(), 256);
+    }
+}
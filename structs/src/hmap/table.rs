@@ -0,0 +1,115 @@
+use std::{borrow::Borrow, hash::Hash};
+
+///Where an entry sits inside a table.
+///Bucket and index for BucketList, slot and 0 for OpenTable.
+pub type Pos = (usize, usize);
+
+pub(super) fn add_count(hist: &mut Vec<usize>, n: usize) {
+    if hist.len() <= n {
+        hist.resize(n + 1, 0);
+    }
+    hist[n] += 1;
+}
+
+///One half of an HMap.
+///The map works out hashes and hands them in,
+///so every table behind one map agrees on where a key belongs.
+///
+///Tables are migrated a step at a time with take_bucket,
+///and must still answer lookups correctly part way through.
+pub trait Table<K, V> {
+    type Iter<'a>: Iterator<Item = &'a (K, V)>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+    type IterMut<'a>: Iterator<Item = &'a mut (K, V)>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+    type IntoIter: Iterator<Item = (K, V)>;
+    ///Takes the entries as it goes, zeroing len up front.
+    ///Dropping it part way must leave the table empty.
+    type Drain<'a>: Iterator<Item = (K, V)>
+    where
+        Self: 'a,
+        K: 'a,
+        V: 'a;
+
+    fn new() -> Self;
+
+    ///Buckets needed to hold n entries without growing
+    fn buckets_for(n: usize, max_bucket: usize) -> usize;
+
+    ///Entries that fit in nb buckets without growing
+    fn capacity_of(nb: usize, max_bucket: usize) -> usize;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn b_len(&self) -> usize;
+
+    ///Sets the bucket count to exactly n.
+    ///Only call this when the table is empty, as nothing is rehashed.
+    fn set_buckets(&mut self, n: usize);
+
+    fn find<KB>(&self, h: u64, k: &KB) -> Option<Pos>
+    where
+        K: Borrow<KB>,
+        KB: Hash + Eq + ?Sized;
+
+    ///Adds an entry whose key is known not to be present
+    fn push(&mut self, h: u64, k: K, v: V) -> Pos;
+
+    fn at(&self, p: Pos) -> &(K, V);
+
+    fn at_mut(&mut self, p: Pos) -> &mut (K, V);
+
+    fn remove_at(&mut self, p: Pos) -> (K, V);
+
+    ///True if adding an entry that hashes to h should make the map grow
+    fn crowded(&self, h: u64, max_bucket: usize) -> bool;
+
+    ///How many entries a lookup walks to reach the one at p
+    fn walk_len(&self, p: Pos) -> usize;
+
+    ///Adds to hist[n] for each bucket whose lookups walk n entries.
+    ///For an OpenTable that is each entry's distance from home plus one,
+    ///with empty slots counted at 0.
+    fn histogram(&self, hist: &mut Vec<usize>);
+
+    ///Most entries the table can hold at all, or None if it only slows down
+    fn limit(&self) -> Option<usize>;
+
+    ///Removes and returns the entries for step n of a migration,
+    ///or None once n is past the last bucket.
+    ///Steps are taken in order from 0, and together cover every bucket.
+    fn take_bucket(&mut self, n: usize) -> Option<Vec<(K, V)>>;
+
+    fn retain<F>(&mut self, f: &mut F)
+    where
+        F: FnMut(&K, &mut V) -> bool;
+
+    fn clear(&mut self);
+
+    fn iter(&self) -> Self::Iter<'_>;
+
+    fn iter_mut(&mut self) -> Self::IterMut<'_>;
+
+    fn into_iter(self) -> Self::IntoIter;
+
+    fn drain(&mut self) -> Self::Drain<'_>;
+
+    fn remove<KB>(&mut self, h: u64, k: &KB) -> Option<(K, V)>
+    where
+        K: Borrow<KB>,
+        KB: Hash + Eq + ?Sized,
+    {
+        let p = self.find(h, k)?;
+        Some(self.remove_at(p))
+    }
+}
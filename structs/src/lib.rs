@@ -16,12 +16,58 @@ use std::{cell::RefCell, rc::Rc};
 
 pub use graph::Graph;
 pub use hmap::hash;
+pub use hmap::quality;
+pub use hmap::BucketList;
+pub use hmap::ConcurrentHMap;
+pub use hmap::Difference;
+pub use hmap::Drain;
+pub use hmap::Entry;
 pub use hmap::HMap;
+pub use hmap::HMapStats;
+pub use hmap::HSet;
+pub use hmap::Intersection;
+pub use hmap::IntoIter;
+pub use hmap::Iter;
+pub use hmap::IterMut;
+pub use hmap::Keys;
+pub use hmap::LruHMap;
+pub use hmap::LruValueMut;
+pub use hmap::MHash;
+pub use hmap::MapHasher;
+pub use hmap::OccupiedEntry;
+pub use hmap::OpenHMap;
+pub use hmap::OpenTable;
+pub use hmap::RandomHasher;
+pub use hmap::RandomMHash;
+pub use hmap::Reseed;
+pub use hmap::SetIntoIter;
+pub use hmap::SetIter;
+pub use hmap::ShardRef;
+pub use hmap::Sorted;
+pub use hmap::SymmetricDifference;
+pub use hmap::Table;
+pub use hmap::Union;
+pub use hmap::VacantEntry;
+pub use hmap::Values;
+pub use hmap::ValuesMut;
 pub use lists::DbList;
 pub use lists::LinkedList;
 pub use storage::Blob;
 pub use storage::BlobError;
+pub use storage::BlobMap;
+#[cfg(feature = "mmap")]
+pub use storage::BlobRef;
 pub use storage::BlobStore;
+pub use storage::Blobs;
+pub use storage::BlockStats;
+pub use storage::Codec;
+pub use storage::GrowingBlobStore;
+#[cfg(feature = "mmap")]
+pub use storage::MappedBlobStore;
+pub use storage::ReadOnlyBlobStore;
+pub use storage::Schema;
+pub use storage::Transaction;
+pub use storage::VerifyReport;
 pub use tree::BalancedTree;
 pub use tree::BinTree;
 pub use tree::HuffEncodedString;
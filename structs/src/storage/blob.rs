@@ -1,8 +1,13 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+};
+
 use serde::{Deserialize, Serialize};
 
 use crate::hmap;
 
-use super::error::BlobError;
+use super::{codec::Codec, error::BlobError};
 
 pub fn read_u64<R: std::io::Read>(r: &mut R) -> Result<u64, BlobError> {
     let mut buf = [0u8; 8];
@@ -14,9 +19,152 @@ pub fn write_u64<W: std::io::Write>(w: &mut W, dat: u64) -> Result<(), BlobError
     Ok(w.write_all(&ec)?)
 }
 
+///Reads a file from a position of its own, with pread,
+///so any number can read one file at once through a shared reference
+pub struct ReadAt<'a> {
+    file: &'a File,
+    pos: u64,
+}
+
+impl<'a> ReadAt<'a> {
+    pub fn new(file: &'a File, pos: u64) -> Self {
+        ReadAt { file, pos }
+    }
+}
+
+impl Read for ReadAt<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(unix)]
+        let n = std::os::unix::fs::FileExt::read_at(self.file, buf, self.pos)?;
+        #[cfg(windows)]
+        let n = std::os::windows::fs::FileExt::seek_read(self.file, buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for ReadAt<'_> {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        let pos = match to {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.file.metadata()?.len().checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        };
+        self.pos = pos.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "bad seek"))?;
+        Ok(self.pos)
+    }
+}
+
+///Bytes before each record's key: klen, vlen, then a check word.
+///The check's high half is a CRC32 of the lengths, so a walk can trust them
+///without reading the body, and its low half is a CRC32 of the key and value.
+pub const REC_HEAD: u64 = 24;
+
+///Set in a record's klen on disk when it is a stub,
+///whose value is where its overflow pages are rather than the value itself
+const STUB: u64 = 1 << 63;
+
+///Set in a record's klen on disk when its value is packed, see Codec
+const PACKED: u64 = 1 << 62;
+
+///The start of a record, giving its size.  klen is 0 for a gap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecHead {
+    pub klen: u64,
+    pub vlen: u64,
+    pub stub: bool,
+    pub packed: bool,
+    body_crc: u32,
+}
+
+///The hash that picks a key's block, from its serialized bytes
+pub fn key_hash(k: &[u8], seed: u64) -> u64 {
+    hmap::hash(seed, k)
+}
+
+fn body_crc(k: &[u8], v: &[u8]) -> u32 {
+    let mut h = crc32fast::Hasher::new();
+    h.update(k);
+    h.update(v);
+    h.finalize()
+}
+
+fn head_crc(klen: u64, vlen: u64) -> u32 {
+    let mut h = crc32fast::Hasher::new();
+    h.update(&klen.to_le_bytes());
+    h.update(&vlen.to_le_bytes());
+    h.finalize()
+}
+
+impl RecHead {
+    ///A gap with vlen free bytes after its head
+    pub fn gap(vlen: u64) -> Self {
+        RecHead {
+            klen: 0,
+            vlen,
+            stub: false,
+            packed: false,
+            body_crc: 0,
+        }
+    }
+
+    ///Reads the head of the record at offset, failing with Corrupt if the lengths
+    ///don't match their check
+    pub fn read<R: std::io::Read>(r: &mut R, offset: u64) -> Result<Self, BlobError> {
+        let klen = read_u64(r)?;
+        let vlen = read_u64(r)?;
+        let check = read_u64(r)?;
+        if (check >> 32) as u32 != head_crc(klen, vlen) {
+            return Err(BlobError::Corrupt { offset });
+        }
+        Ok(RecHead {
+            klen: klen & !(STUB | PACKED),
+            vlen,
+            stub: klen & STUB != 0,
+            packed: klen & PACKED != 0,
+            body_crc: check as u32,
+        })
+    }
+
+    pub fn out<W: std::io::Write>(&self, w: &mut W) -> Result<(), BlobError> {
+        let mut klen = self.klen;
+        if self.stub {
+            klen |= STUB;
+        }
+        if self.packed {
+            klen |= PACKED;
+        }
+        write_u64(w, klen)?;
+        write_u64(w, self.vlen)?;
+        let check = (head_crc(klen, self.vlen) as u64) << 32 | self.body_crc as u64;
+        write_u64(w, check)
+    }
+
+    ///Whether k and v are the key and value this head was written for
+    pub fn checks(&self, k: &[u8], v: &[u8]) -> bool {
+        k.len() as u64 == self.klen
+            && v.len() as u64 == self.vlen
+            && body_crc(k, v) == self.body_crc
+    }
+
+    ///Bytes the whole record takes, head included
+    pub fn size(&self) -> u64 {
+        REC_HEAD + self.klen + self.vlen
+    }
+
+    pub fn is_gap(&self) -> bool {
+        self.klen == 0
+    }
+}
+
+#[derive(Clone)]
 pub struct Blob {
     k: Vec<u8>,
     v: Vec<u8>,
+    ///v is the first overflow page and length of the real value
+    stub: bool,
+    ///The real value is packed, see Codec
+    packed: bool,
 }
 
 impl Blob {
@@ -24,25 +172,129 @@ impl Blob {
         Ok(Blob {
             k: bincode::serialize(k)?,
             v: bincode::serialize(v)?,
+            stub: false,
+            packed: false,
         })
     }
 
+    ///A blob of a key and value already serialized
+    pub fn from_bytes(k: Vec<u8>, v: Vec<u8>) -> Blob {
+        Blob {
+            k,
+            v,
+            stub: false,
+            packed: false,
+        }
+    }
+
+    ///A stub standing in for this blob, whose value is in the overflow pages from first
+    pub fn stub(&self, first: u64) -> Result<Blob, BlobError> {
+        Ok(Blob {
+            k: self.k.clone(),
+            v: bincode::serialize(&(first, self.v.len() as u64))?,
+            stub: true,
+            packed: self.packed,
+        })
+    }
+
+    pub fn is_stub(&self) -> bool {
+        self.stub
+    }
+
+    ///The first overflow page and length of a stub's value
+    pub fn stub_target(&self) -> Result<(u64, u64), BlobError> {
+        Ok(bincode::deserialize(&self.v)?)
+    }
+
+    ///The blob a stub stands for, given its value
+    pub fn unstub(self, v: Vec<u8>) -> Blob {
+        Blob {
+            v,
+            stub: false,
+            ..self
+        }
+    }
+
+    ///This blob with its value packed by codec, unless that would be no smaller
+    pub fn pack(self, codec: Codec) -> Blob {
+        if self.packed || self.stub {
+            return self;
+        }
+        match codec.pack(&self.v) {
+            Some(v) => Blob {
+                v,
+                packed: true,
+                ..self
+            },
+            None => self,
+        }
+    }
+
+    ///This blob with its value as it was before packing.
+    ///A store unpacks each blob as it reads it, so get_v can borrow from the value.
+    pub fn unpack(self) -> Result<Blob, BlobError> {
+        if !self.packed || self.stub {
+            return Ok(self);
+        }
+        Ok(Blob {
+            v: Codec::unpack(&self.v)?,
+            packed: false,
+            ..self
+        })
+    }
+
+    ///The serialized value
+    pub fn value(&self) -> &[u8] {
+        &self.v
+    }
+
+    fn head(&self) -> RecHead {
+        RecHead {
+            klen: self.k.len() as u64,
+            vlen: self.v.len() as u64,
+            stub: self.stub,
+            packed: self.packed,
+            body_crc: body_crc(&self.k, &self.v),
+        }
+    }
+
     pub fn out<W: std::io::Write>(&self, w: &mut W) -> Result<(), BlobError> {
-        write_u64(w, self.k.len() as u64)?;
-        write_u64(w, self.v.len() as u64)?;
+        self.head().out(w)?;
         w.write_all(&self.k)?;
         w.write_all(&self.v)?;
         Ok(())
     }
 
-    pub fn read<R: std::io::Read>(r: &mut R) -> Result<Blob, BlobError> {
-        let klen = read_u64(r)? as usize;
-        let vlen = read_u64(r)? as usize;
-        let mut k = vec![0u8; klen];
-        let mut v = vec![0u8; vlen];
+    ///Reads a whole record, which must be a blob rather than a gap, at offset
+    pub fn read<R: std::io::Read>(r: &mut R, offset: u64) -> Result<Blob, BlobError> {
+        let head = RecHead::read(r, offset)?;
+        Self::read_body(r, &head, offset)
+    }
+
+    ///Reads the key and value following head, for the record at offset
+    pub fn read_body<R: std::io::Read>(
+        r: &mut R,
+        head: &RecHead,
+        offset: u64,
+    ) -> Result<Blob, BlobError> {
+        let mut k = vec![0u8; head.klen as usize];
+        let mut v = vec![0u8; head.vlen as usize];
         r.read_exact(&mut k)?;
         r.read_exact(&mut v)?;
-        Ok(Blob { k, v })
+        let res = Blob {
+            k,
+            v,
+            stub: head.stub,
+            packed: head.packed,
+        };
+        if res.head() != *head {
+            return Err(BlobError::Corrupt { offset });
+        }
+        Ok(res)
+    }
+
+    pub fn get_k<'a, K: Deserialize<'a>>(&'a self) -> Result<K, BlobError> {
+        Ok(bincode::deserialize(&self.k)?)
     }
 
     pub fn get_v<'a, V: Deserialize<'a>>(&'a self) -> Result<V, BlobError> {
@@ -50,11 +302,16 @@ impl Blob {
     }
 
     pub fn len(&self) -> u64 {
-        (16 + self.k.len() + self.v.len()) as u64
+        REC_HEAD + (self.k.len() + self.v.len()) as u64
+    }
+
+    ///A blob with no key marks a gap in a block
+    pub fn is_empty(&self) -> bool {
+        self.k.is_empty()
     }
 
     pub fn k_hash(&self, seed: u64) -> u64 {
-        hmap::hash(seed, &self.k)
+        key_hash(&self.k, seed)
     }
 
     pub fn key_match(&self, rhs: &Self) -> bool {
@@ -73,6 +330,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::suspicious_open_options)]
     fn test_read_write_string() {
         let tfile = "test_data/t_read_write_string";
         std::fs::remove_file(tfile).ok();
@@ -89,8 +347,26 @@ mod test {
             blob.out(&mut fout).unwrap();
         }
         let mut fin = std::fs::File::open(tfile).unwrap();
-        let b2 = Blob::read(&mut fin).unwrap();
+        let b2 = Blob::read(&mut fin, 0).unwrap();
         let v2: String = b2.get_v().unwrap();
         assert_eq!(v, &v2);
     }
+
+    #[test]
+    fn test_read_checks_record() {
+        let p = Point { x: 3, y: 4 };
+        let blob = Blob::from(&87_i32, &p).unwrap();
+        let mut buf = Vec::new();
+        blob.out(&mut buf).unwrap();
+        let b2 = Blob::read(&mut &buf[..], 40).unwrap();
+        assert_eq!(b2.get_v::<Point<i32>>().unwrap(), p);
+
+        //a changed value byte fails the body's check, at the record's own offset
+        let last = buf.len() - 1;
+        buf[last] ^= 1;
+        assert!(matches!(
+            Blob::read(&mut &buf[..], 40),
+            Err(BlobError::Corrupt { offset: 40 })
+        ));
+    }
 }
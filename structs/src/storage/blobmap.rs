@@ -0,0 +1,255 @@
+use std::marker::PhantomData;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{BlobError, BlobStore};
+
+use super::blob;
+
+///A BlobStore that only holds keys of type K and values of type V.
+///The fingerprint of K's and V's schemas goes in the file's tag for open to check.
+pub struct BlobMap<K, V> {
+    store: BlobStore,
+    kv: PhantomData<fn(K) -> V>,
+}
+
+///Names the serialized form of a type a BlobMap stores.
+///Types built from others, like a Vec or a tuple, name theirs from their parts.
+///For a type of your own give a name and a version, such as "Point v1",
+///and change it when the type's serialized form changes.
+pub trait Schema {
+    fn schema() -> String;
+}
+
+macro_rules! schema_by_name {
+    ($($t:ty),*) => {
+        $(impl Schema for $t {
+            fn schema() -> String {
+                stringify!($t).to_string()
+            }
+        })*
+    };
+}
+
+schema_by_name!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    String
+);
+
+impl<T: Schema> Schema for Vec<T> {
+    fn schema() -> String {
+        format!("Vec<{}>", T::schema())
+    }
+}
+
+impl<T: Schema> Schema for Option<T> {
+    fn schema() -> String {
+        format!("Option<{}>", T::schema())
+    }
+}
+
+impl<T: Schema, const N: usize> Schema for [T; N] {
+    fn schema() -> String {
+        format!("[{}; {}]", T::schema(), N)
+    }
+}
+
+impl<A: Schema, B: Schema> Schema for (A, B) {
+    fn schema() -> String {
+        format!("({}, {})", A::schema(), B::schema())
+    }
+}
+
+impl<A: Schema, B: Schema, C: Schema> Schema for (A, B, C) {
+    fn schema() -> String {
+        format!("({}, {}, {})", A::schema(), B::schema(), C::schema())
+    }
+}
+
+///Made from K's and V's schemas alone, so the same on any build.
+///Never 0, which marks an untyped store.
+pub fn fingerprint<K: Schema, V: Schema>() -> u64 {
+    let schema = format!("{} -> {}", K::schema(), V::schema());
+    blob::key_hash(schema.as_bytes(), 0).max(1)
+}
+
+impl<K, V> BlobMap<K, V>
+where
+    K: Serialize + DeserializeOwned + Schema,
+    V: Serialize + DeserializeOwned + Schema,
+{
+    pub fn new(fname: &str, block_size: u64, nblocks: u64) -> Result<Self, BlobError> {
+        let mut store = BlobStore::new(fname, block_size, nblocks)?;
+        store.set_tag(fingerprint::<K, V>())?;
+        Ok(BlobMap {
+            store,
+            kv: PhantomData,
+        })
+    }
+
+    ///Fails with WrongTypes unless the store was made for K and V
+    pub fn open(fname: &str) -> Result<Self, BlobError> {
+        let store = BlobStore::open(fname)?;
+        let expected = fingerprint::<K, V>();
+        if store.tag() != expected {
+            return Err(BlobError::WrongTypes {
+                expected,
+                found: store.tag(),
+            });
+        }
+        Ok(BlobMap {
+            store,
+            kv: PhantomData,
+        })
+    }
+
+    pub fn new_or_open(fname: &str, block_size: u64, nblocks: u64) -> Result<Self, BlobError> {
+        Self::new(fname, block_size, nblocks).or_else(|_| Self::open(fname))
+    }
+
+    ///Returns the value k had before, if any
+    pub fn insert(&mut self, k: &K, v: &V) -> Result<Option<V>, BlobError> {
+        let old = self.get(k)?;
+        self.store.insert(k, v)?;
+        Ok(old)
+    }
+
+    pub fn get(&self, k: &K) -> Result<Option<V>, BlobError> {
+        match self.store.get(k) {
+            Ok(b) => Ok(Some(b.get_v()?)),
+            Err(BlobError::NotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn contains_key(&self, k: &K) -> Result<bool, BlobError> {
+        Ok(self.get(k)?.is_some())
+    }
+
+    ///Returns the value k had, if any
+    pub fn remove(&mut self, k: &K) -> Result<Option<V>, BlobError> {
+        let old = self.get(k)?;
+        if old.is_some() {
+            self.store.remove(k)?;
+        }
+        Ok(old)
+    }
+
+    ///Every entry, in file order
+    pub fn iter(&self) -> impl Iterator<Item = Result<(K, V), BlobError>> + '_ {
+        self.store.iter().pairs()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = Result<K, BlobError>> + '_ {
+        self.store.iter().keys()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.store.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.store.is_empty()
+    }
+
+    pub fn into_inner(self) -> BlobStore {
+        self.store
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    impl Schema for Point {
+        fn schema() -> String {
+            "Point v1".to_string()
+        }
+    }
+
+    #[test]
+    fn test_typed_insert_get() {
+        let fs = "test_data/blobmap_typed";
+        std::fs::remove_file(fs).ok();
+        let mut bm: BlobMap<String, Point> = BlobMap::new(fs, 1000, 10).unwrap();
+        let p = Point { x: 3, y: 4 };
+        assert_eq!(bm.insert(&"a".to_string(), &p).unwrap(), None);
+        let q = Point { x: 5, y: 6 };
+        assert_eq!(bm.insert(&"a".to_string(), &q).unwrap(), Some(p));
+        assert_eq!(bm.get(&"a".to_string()).unwrap(), Some(q.clone()));
+        assert_eq!(bm.get(&"b".to_string()).unwrap(), None);
+        assert_eq!(bm.len(), 1);
+        let all: Vec<(String, Point)> = bm.iter().map(|kv| kv.unwrap()).collect();
+        assert_eq!(all, vec![("a".to_string(), q.clone())]);
+        drop(bm);
+
+        let mut bm: BlobMap<String, Point> = BlobMap::open(fs).unwrap();
+        assert_eq!(bm.remove(&"a".to_string()).unwrap(), Some(q));
+        assert_eq!(bm.remove(&"a".to_string()).unwrap(), None);
+        assert!(bm.is_empty());
+    }
+
+    #[test]
+    fn test_wrong_types() {
+        let fs = "test_data/blobmap_wrong";
+        std::fs::remove_file(fs).ok();
+        BlobMap::<String, Point>::new(fs, 1000, 10).unwrap();
+        assert!(matches!(
+            BlobMap::<i32, i32>::open(fs),
+            Err(BlobError::WrongTypes { .. })
+        ));
+        //the same types the other way round
+        assert!(matches!(
+            BlobMap::<Point, String>::open(fs),
+            Err(BlobError::WrongTypes { .. })
+        ));
+        assert!(matches!(
+            BlobMap::<String, Vec<Point>>::open(fs),
+            Err(BlobError::WrongTypes { .. })
+        ));
+        assert!(BlobMap::<String, Point>::open(fs).is_ok());
+        //the tag comes from the schemas, whatever the types are called on this build
+        let store = BlobStore::open(fs).unwrap();
+        assert_eq!(store.tag(), fingerprint::<String, Point>());
+        assert_eq!(fingerprint::<String, Point>(), 0xbfb2_4b63_e3d5_cbe0);
+
+        //an untyped store has no fingerprint to match
+        let fs = "test_data/blobmap_untyped";
+        std::fs::remove_file(fs).ok();
+        BlobStore::new(fs, 1000, 10).unwrap();
+        let e = BlobMap::<i32, i32>::open(fs).err().unwrap();
+        assert!(matches!(e, BlobError::WrongTypes { found: 0, .. }));
+    }
+
+    #[test]
+    fn test_schemas() {
+        assert_eq!(
+            <Vec<(u8, Option<String>)>>::schema(),
+            "Vec<(u8, Option<String>)>"
+        );
+        assert_eq!(<[Point; 2]>::schema(), "[Point v1; 2]");
+    }
+}
@@ -1,146 +1,631 @@
 use std::{
-    fs::{File, OpenOptions},
-    io::{Seek, SeekFrom},
+    fs::{File, OpenOptions, TryLockError},
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
 };
 
 use serde::Serialize;
 
 use crate::{Blob, BlobError};
 
-use super::blob::{read_u64, write_u64};
+use super::{
+    blob::{read_u64, write_u64, ReadAt, RecHead, REC_HEAD},
+    codec::Codec,
+    header::{self, Header},
+    iter::Blobs,
+    legacy,
+    overflow::{Pages, Writes},
+    transaction::{Op, Transaction},
+    wal::{self, Wal},
+};
+
+///Bytes before the first block, see Header
+pub(super) const CONT_SIZE: u64 = header::SIZE;
 
-const CONT_SIZE: u64 = 32;
+///How many times upgrade doubles the blocks looking for room for every blob
+const UPGRADE_TRIES: usize = 8;
 
 ///This blob store will act as one half of the hashmap
-/// as with hashmap, wrap this in something to make growing work
+/// as with hashmap, it has a fixed number of blocks, GrowingBlobStore wraps it to grow.
+/// A blob too big for a block keeps its value in overflow pages after the blocks.
+///
+/// Reads take &self and use positional reads, so behind a RwLock any number of
+/// threads can read while one writes.  An advisory lock on the file keeps
+/// any other store, in this process or another, from opening it meanwhile.
+/// To share the file between readers, open it with ReadOnlyBlobStore instead.
 pub struct BlobStore {
     file: File,
     hseed: u64,
     block_size: u64,
     nblocks: u64,
     elems: u64,
+    ///The header's flags, saying what the file holds beyond the basic format
+    flags: u64,
+    ///What the store holds, for wrappers like BlobMap to check on open.  0 if unset.
+    tag: u64,
+    ///The first overflow page free for reuse, 0 if none
+    free_page: u64,
+    wal: Option<Wal>,
+    wal_name: String,
+    ///While an atomic op runs, the file's length and the bytes it has put back if it fails
+    undo: Option<(u64, wal::Ranges)>,
+    ///Fragmentation above which a block is packed, see set_auto_compact
+    auto_compact: Option<f64>,
+    ///Steps left before a simulated crash
+    #[cfg(test)]
+    fault: Option<usize>,
+}
+
+///What BlobStore::verify found
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct VerifyReport {
+    ///Offsets of records that failed their check, or whose overflow pages did,
+    ///and 0 if the header's chain of free pages is broken.
+    ///A record whose lengths fail hides where the next starts,
+    ///so the bytes up to the next head that checks out are lost with it.
+    pub bad: Vec<u64>,
+    ///Blobs that passed
+    pub live: u64,
+    ///The count of blobs the header gives
+    pub elems: u64,
+    ///Overflow pages neither holding a value nor free,
+    ///as a crash without the WAL can leave.  repair frees them.
+    pub lost_pages: u64,
+}
+
+///How one block's space is split up, from BlobStore::block_stats
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BlockStats {
+    pub blobs: u64,
+    ///Bytes taken by blobs, heads included
+    pub used: u64,
+    pub gaps: u64,
+    ///The biggest blob that fits any one gap
+    pub largest_gap: u64,
+    ///The biggest blob that would fit once the block is packed
+    pub free: u64,
+}
+
+impl BlockStats {
+    ///0 while the free space is one gap, towards 1 as it splits into small ones
+    pub fn fragmentation(&self) -> f64 {
+        match self.free {
+            0 => 0.0,
+            f => 1.0 - self.largest_gap as f64 / f as f64,
+        }
+    }
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.bad.is_empty() && self.live == self.elems && self.lost_pages == 0
+    }
+}
+
+fn wal_name(fname: &str) -> String {
+    format!("{}.wal", fname)
+}
+
+///Fails with Unrecovered if fname's WAL holds a change a crash cut short,
+///which only a BlobStore opened to write can undo
+pub(super) fn check_recovered(fname: &str) -> Result<(), BlobError> {
+    if let Ok(buf) = std::fs::read(wal_name(fname)) {
+        if wal::unfinished(&buf).is_some() {
+            return Err(BlobError::Unrecovered);
+        }
+    }
+    Ok(())
+}
+
+///Takes the file's advisory lock, held until it is closed, or fails with Locked
+fn lock(file: &File) -> Result<(), BlobError> {
+    locked(file.try_lock())
+}
+
+///Takes the file's advisory lock shared with other readers, held until it is closed,
+///or fails with Locked if a writer has it
+pub(super) fn lock_shared(file: &File) -> Result<(), BlobError> {
+    locked(file.try_lock_shared())
+}
+
+fn locked(r: Result<(), TryLockError>) -> Result<(), BlobError> {
+    match r {
+        Ok(()) => Ok(()),
+        Err(TryLockError::WouldBlock) => Err(BlobError::Locked),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
 }
 
 impl BlobStore {
     pub fn new(fname: &str, block_size: u64, nblocks: u64) -> Result<Self, BlobError> {
-        let hseed = rand::random::<u64>();
-        let mut ff = OpenOptions::new()
+        Self::with_seed(fname, block_size, nblocks, rand::random::<u64>())
+    }
+
+    ///A store hashing with hseed, so that with twice the blocks
+    ///block n of another store with that seed splits into blocks n and n + nblocks
+    pub(super) fn with_seed(
+        fname: &str,
+        block_size: u64,
+        nblocks: u64,
+        hseed: u64,
+    ) -> Result<Self, BlobError> {
+        let file = OpenOptions::new()
             .create_new(true)
             .write(true)
             .read(true)
             .open(fname)?;
+        lock(&file)?;
+        file.set_len(CONT_SIZE + block_size * nblocks)?;
+        //left by a store that used to have this name
+        std::fs::remove_file(wal_name(fname)).ok();
 
-        let f = &mut ff;
-        f.set_len(CONT_SIZE + block_size * nblocks)?;
-        f.seek(SeekFrom::Start(0))?;
-        write_u64(f, hseed)?;
-        write_u64(f, block_size)?;
-        write_u64(f, nblocks)?;
-        write_u64(f, 0)?;
-
+        let mut res = Self {
+            hseed,
+            file,
+            block_size,
+            nblocks,
+            elems: 0,
+            flags: header::CHECKSUMS,
+            tag: 0,
+            free_page: 0,
+            wal: None,
+            wal_name: wal_name(fname),
+            undo: None,
+            auto_compact: None,
+            #[cfg(test)]
+            fault: None,
+        };
+        res.write_header()?;
         for x in 0..nblocks {
-            f.seek(SeekFrom::Start(CONT_SIZE + x * block_size))?;
-            write_u64(f, 0)?;
-            write_u64(f, block_size - 16)?;
+            res.write_gap(res.b_start(x), block_size - REC_HEAD)?;
         }
-
-        Ok({
-            Self {
-                hseed,
-                file: ff,
-                block_size,
-                nblocks,
-                elems: 0,
-            }
-        })
+        Ok(res)
     }
 
+    ///Opens fname, first undoing any change a crash cut short if it has a WAL.
+    ///The WAL stays on.
     pub fn open(fname: &str) -> Result<Self, BlobError> {
         let mut ff = OpenOptions::new().write(true).read(true).open(fname)?;
-        let f = &mut ff;
-        f.seek(SeekFrom::Start(0))?;
-        let hseed = read_u64(f)?;
-        let block_size = read_u64(f)?;
-        let nblocks = read_u64(f)?;
-        let elems = read_u64(f)?;
+        //before recovery, which must not run under another store's feet
+        lock(&ff)?;
+        let wal = match Path::new(&wal_name(fname)).exists() {
+            true => {
+                let mut wal = Wal::open(&wal_name(fname))?;
+                if let Some((flen, ranges)) = wal.unfinished()? {
+                    for (pos, b) in ranges.iter().rev() {
+                        ff.seek(SeekFrom::Start(*pos))?;
+                        ff.write_all(b)?;
+                    }
+                    ff.set_len(flen)?;
+                    ff.sync_data()?;
+                }
+                wal.checkpoint()?;
+                Some(wal)
+            }
+            false => None,
+        };
+        Self::from_file(ff, wal, fname)
+    }
+
+    ///Opens fname to read only, under a lock shared with other readers.
+    ///Fails with Unrecovered if a crash left a change for the WAL to undo,
+    ///as only a store open to write can.
+    pub(super) fn open_shared(fname: &str) -> Result<Self, BlobError> {
+        let ff = File::open(fname)?;
+        lock_shared(&ff)?;
+        check_recovered(fname)?;
+        Self::from_file(ff, None, fname)
+    }
+
+    fn from_file(mut ff: File, wal: Option<Wal>, fname: &str) -> Result<Self, BlobError> {
+        let h = Header::read(&mut ff)?;
         Ok(Self {
-            hseed,
+            hseed: h.hseed,
             file: ff,
-            block_size,
-            nblocks,
-            elems,
+            block_size: h.block_size,
+            nblocks: h.nblocks,
+            elems: h.elems,
+            flags: h.flags,
+            tag: h.tag,
+            free_page: h.free_page,
+            wal,
+            wal_name: wal_name(fname),
+            undo: None,
+            auto_compact: None,
+            #[cfg(test)]
+            fault: None,
         })
     }
 
+    ///Rewrites fname, a store from before format versions, in the current format.
+    ///Record heads are bigger now, so when a block no longer holds its blobs
+    ///the new store doubles its blocks, keeping the seed and block size.
+    ///It is built beside fname then renamed over it, so a crash leaves one or the other.
+    ///A store already in the current format is left alone.
+    pub fn upgrade(fname: &str) -> Result<(), BlobError> {
+        let mut f = OpenOptions::new().read(true).write(true).open(fname)?;
+        lock(&f)?;
+        match Header::read(&mut f) {
+            Err(BlobError::NotAStore) => {}
+            r => return r.map(|_| ()),
+        }
+        let mut buf = Vec::new();
+        f.seek(SeekFrom::Start(0))?;
+        f.read_to_end(&mut buf)?;
+        let old = legacy::read(&buf)?;
+
+        let tmp = format!("{}.upgrade", fname);
+        let mut nblocks = old.nblocks;
+        for _ in 0..UPGRADE_TRIES {
+            std::fs::remove_file(&tmp).ok();
+            let res =
+                Self::with_seed(&tmp, old.block_size, nblocks, old.hseed).and_then(|mut s| {
+                    old.blobs.iter().try_for_each(|b| s.insert_blob(b))?;
+                    Ok(s.file.sync_all()?)
+                });
+            match res {
+                Ok(()) => return Ok(std::fs::rename(&tmp, fname)?),
+                Err(BlobError::NoRoom) => nblocks *= 2,
+                Err(e) => {
+                    std::fs::remove_file(&tmp).ok();
+                    return Err(e);
+                }
+            }
+        }
+        std::fs::remove_file(&tmp).ok();
+        Err(BlobError::NoRoom)
+    }
+
+    fn write_header(&mut self) -> Result<(), BlobError> {
+        let h = Header {
+            flags: self.flags,
+            hseed: self.hseed,
+            block_size: self.block_size,
+            nblocks: self.nblocks,
+            elems: self.elems,
+            tag: self.tag,
+            free_page: self.free_page,
+        }
+        .bytes()?;
+        self.file.seek(SeekFrom::Start(0))?;
+        Ok(self.file.write_all(&h)?)
+    }
+
     pub fn inc_elems(&mut self, n: i32) -> Result<(), BlobError> {
         if n > 0 {
             self.elems += n as u64;
         } else {
-            let n2 = (-n) as u64;
-            if self.elems > n2 {
-                self.elems -= n2;
+            self.elems = self.elems.saturating_sub((-n) as u64);
+        }
+        self.write_header()
+    }
+
+    ///Turns the write-ahead log in `<fname>.wal` on or off.
+    ///With it on, each insert or remove happens whole or not at all,
+    ///even if the process dies part way, at the cost of three syncs a write,
+    ///and one more for each overflow page it reuses.
+    pub fn set_wal(&mut self, on: bool) -> Result<(), BlobError> {
+        match (on, self.wal.is_some()) {
+            (true, false) => self.wal = Some(Wal::open(&self.wal_name)?),
+            (false, true) => {
+                self.wal = None;
+                std::fs::remove_file(&self.wal_name)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn has_wal(&self) -> bool {
+        self.wal.is_some()
+    }
+
+    ///Points the store at fname once its file has been renamed there,
+    ///moving the WAL, which is empty between ops, along with it
+    pub(super) fn renamed(&mut self, fname: &str) -> Result<(), BlobError> {
+        let on = self.has_wal();
+        self.set_wal(false)?;
+        self.wal_name = wal_name(fname);
+        self.set_wal(on)
+    }
+
+    ///Runs op, which may write to the header and the given blocks,
+    ///and to overflow pages through write_at, whole or not at all.
+    ///Their old bytes are put back if op fails, and the file cut back to its length,
+    ///and with the WAL on are logged first, so a crash part way is undone on open.
+    fn atomic<R, F>(&mut self, blocks: &[u64], op: F) -> Result<R, BlobError>
+    where
+        F: FnOnce(&mut Self) -> Result<R, BlobError>,
+    {
+        let flen = self.file.metadata()?.len();
+        let mut ranges = vec![(0, self.read_range(0, CONT_SIZE)?)];
+        for &n in blocks {
+            let b_start = self.b_start(n);
+            ranges.push((b_start, self.read_range(b_start, self.block_size)?));
+        }
+        if self.wal.is_some() {
+            let rec = wal::encode(flen, &ranges)?;
+            self.wal().append(&rec)?;
+            self.wal().sync()?;
+            self.step()?;
+        }
+
+        self.undo = Some((flen, ranges));
+        let res = op(self);
+        let (flen, ranges) = self.undo.take().expect("set above");
+        if res.is_err() && !self.crashed() {
+            for (pos, b) in ranges.iter().rev() {
+                self.file.seek(SeekFrom::Start(*pos))?;
+                self.file.write_all(b)?;
+            }
+            self.file.set_len(flen)?;
+            let h = Header::read(&mut self.file)?;
+            (self.elems, self.tag, self.free_page) = (h.elems, h.tag, h.free_page);
+        }
+        if self.wal.is_some() {
+            self.step()?;
+            self.file.sync_data()?;
+            self.step()?;
+            self.wal().checkpoint()?;
+        }
+        res
+    }
+
+    ///Writes b at pos, first logging the bytes there if an atomic op
+    ///hasn't already.  Those past the file's end when it started need no logging,
+    ///as undoing it cuts them off.
+    fn write_at(&mut self, pos: u64, b: &[u8]) -> Result<(), BlobError> {
+        if let Some((flen, ranges)) = &self.undo {
+            let end = pos + b.len() as u64;
+            let logged = ranges
+                .iter()
+                .any(|(p, old)| *p <= pos && end <= p + old.len() as u64);
+            if pos < *flen && !logged {
+                let flen = *flen;
+                let old = self.read_range(pos, b.len() as u64)?;
+                if self.wal.is_some() {
+                    let rec = wal::encode(flen, &[(pos, old.clone())])?;
+                    self.wal().append(&rec)?;
+                    self.wal().sync()?;
+                    self.step()?;
+                }
+                self.undo
+                    .as_mut()
+                    .expect("checked above")
+                    .1
+                    .push((pos, old));
+            }
+        }
+        self.file.seek(SeekFrom::Start(pos))?;
+        Ok(self.file.write_all(b)?)
+    }
+
+    fn wal(&mut self) -> &mut Wal {
+        self.wal.as_mut().expect("only used with the wal on")
+    }
+
+    fn read_range(&mut self, pos: u64, len: u64) -> Result<Vec<u8>, BlobError> {
+        let mut res = vec![0u8; len as usize];
+        self.file.seek(SeekFrom::Start(pos))?;
+        self.file.read_exact(&mut res)?;
+        Ok(res)
+    }
+
+    ///A point where tests can make the store act as if the process died
+    fn step(&mut self) -> Result<(), BlobError> {
+        #[cfg(test)]
+        match &mut self.fault {
+            Some(0) => {
+                let e = std::io::Error::other("simulated crash");
+                return Err(BlobError::IO(e));
             }
+            Some(n) => *n -= 1,
+            None => {}
         }
-        self.file.seek(SeekFrom::Start(24))?;
-        write_u64(&mut self.file, self.elems)?;
         Ok(())
     }
 
+    fn crashed(&self) -> bool {
+        #[cfg(test)]
+        if self.fault == Some(0) {
+            return true;
+        }
+        false
+    }
+
+    ///Simulates a crash after steps more steps, each step failing from then on
+    #[cfg(test)]
+    pub(super) fn crash_after(&mut self, steps: usize) {
+        self.fault = Some(steps);
+    }
+
     pub fn insert<K: Serialize, V: Serialize>(&mut self, k: K, v: V) -> Result<(), BlobError> {
-        self.remove(&k).ok();
-        self.insert_only(k, v)
+        self.replace_blob(&Blob::from(&k, &v)?)
+    }
+
+    ///Takes out any stored blob with blob's key and writes blob, both or neither
+    pub(super) fn replace_blob(&mut self, blob: &Blob) -> Result<(), BlobError> {
+        let blob = blob.clone().pack(self.codec());
+        self.atomic(&[self.block_of(&blob)], |s| {
+            s.del_blob(&blob)?;
+            s.step()?;
+            s.store(&blob)
+        })
     }
 
+    #[cfg(test)]
     fn insert_only<K: Serialize, V: Serialize>(&mut self, k: K, v: V) -> Result<(), BlobError> {
-        let blob = Blob::from(&k, &v)?;
-        if blob.len() > self.block_size {
+        self.insert_blob(&Blob::from(&k, &v)?)
+    }
+
+    ///Buffers inserts and removes to write all at once, see Transaction
+    pub fn transaction(&mut self) -> Transaction<'_> {
+        Transaction::new(self)
+    }
+
+    ///Takes out every key ops touches, then writes every blob they put,
+    ///all of it or on any failure none
+    pub(super) fn apply(&mut self, ops: &[Op]) -> Result<(), BlobError> {
+        let mut blocks: Vec<u64> = ops.iter().map(|op| self.block_of(op.key())).collect();
+        blocks.sort();
+        blocks.dedup();
+        let codec = self.codec();
+        self.atomic(&blocks, |s| {
+            for op in ops {
+                s.del_blob(op.key())?;
+                s.step()?;
+            }
+            for op in ops {
+                if let Op::Put(b) = op {
+                    s.store(&b.clone().pack(codec))?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    ///Writes blob into the first gap in its block with room for it,
+    ///without checking whether the key is already there
+    pub(super) fn insert_blob(&mut self, blob: &Blob) -> Result<(), BlobError> {
+        let blob = blob.clone().pack(self.codec());
+        self.atomic(&[self.block_of(&blob)], |s| s.store(&blob))
+    }
+
+    ///Puts blob in its block, or if it is too big for one
+    ///writes its value to overflow pages and puts a stub in its place
+    fn store(&mut self, blob: &Blob) -> Result<(), BlobError> {
+        if blob.len() + REC_HEAD <= self.block_size {
+            return self.put_blob(blob);
+        }
+        if blob.stub(0)?.len() + REC_HEAD > self.block_size {
             return Err(BlobError::TooBig(blob.len()));
         }
-        let bucket = blob.k_hash(self.hseed) % self.nblocks;
-        let f = &mut self.file;
-        let mut pos = f.seek(SeekFrom::Start(CONT_SIZE + self.block_size * bucket))?;
+        let mut free = self.free_page;
+        let (first, writes) = self
+            .pages()
+            .write(&mut self.at(0), &mut free, blob.value())?;
+        self.free_page = free;
+        self.write_all_at(writes)?;
+        self.write_header()?;
+        self.put_blob(&blob.stub(first)?)
+    }
 
-        loop {
-            if pos > CONT_SIZE + self.block_size * (bucket + 1) {
-                return Err(BlobError::NoRoom);
-            }
-            //Potential bug.  pos is not being set.
-            let klen = read_u64(f)?;
-            let vlen = read_u64(f)?;
-            if klen == 0 && blob.len() < vlen {
-                f.seek(SeekFrom::Start(pos))?;
-                blob.out(f)?;
+    ///The blob b stands for, reading its value from the overflow pages if it is a stub
+    pub(super) fn unspill(&self, b: Blob) -> Result<Blob, BlobError> {
+        if !b.is_stub() {
+            return Ok(b);
+        }
+        let (first, len) = b.stub_target()?;
+        let v = self.pages().read(&mut self.at(0), first, len)?;
+        Ok(b.unstub(v))
+    }
+
+    ///Frees the overflow pages of b, if it is a stub.
+    ///Run in the same atomic op that takes b out, so a rollback brings both back.
+    fn release(&mut self, b: &Blob) -> Result<(), BlobError> {
+        if !b.is_stub() {
+            return Ok(());
+        }
+        let (first, _) = b.stub_target()?;
+        let mut free = self.free_page;
+        let (pos, next) = self.pages().free(&mut self.at(0), &mut free, first)?;
+        self.free_page = free;
+        self.write_at(pos, &next)?;
+        self.write_header()
+    }
+
+    fn write_all_at(&mut self, writes: Writes) -> Result<(), BlobError> {
+        writes
+            .into_iter()
+            .try_for_each(|(pos, b)| self.write_at(pos, &b))
+    }
+
+    ///The overflow pages
+    fn pages(&self) -> Pages {
+        Pages::after(self.b_start(self.nblocks), self.block_size)
+    }
+
+    fn put_blob(&mut self, blob: &Blob) -> Result<(), BlobError> {
+        //the block always keeps a gap's head after the last blob
+        if blob.len() + REC_HEAD > self.block_size {
+            return Err(BlobError::TooBig(blob.len()));
+        }
+        let bucket = self.block_of(blob);
+        let b_end = self.b_start(bucket + 1);
+        let mut pos = self.b_start(bucket);
+
+        while pos < b_end {
+            let h = self.head_at(pos, b_end)?;
+            //the blob fits if the rest of the gap can be a smaller gap
+            if h.is_gap() && blob.len() <= h.vlen {
+                self.file.seek(SeekFrom::Start(pos))?;
+                blob.out(&mut self.file)?;
                 //add pointer immediately after blob ends
-                write_u64(f, 0)?;
-                write_u64(f, (vlen - blob.len()) - 16)?;
-                self.inc_elems(1)?;
-                return Ok(());
+                self.write_gap(pos + blob.len(), h.vlen - blob.len())?;
+                return self.inc_elems(1);
             }
-            pos = f.seek(SeekFrom::Start(pos + 16 + klen + vlen))?;
+            pos += h.size();
+        }
+        if self.auto_compact.is_some() && blob.len() <= self.block_stat(bucket)?.free {
+            self.compact_block(bucket)?;
+            return self.put_blob(blob);
         }
+        Err(BlobError::NoRoom)
+    }
+
+    ///The head of the record at pos, which must end by b_end
+    fn head_at(&self, pos: u64, b_end: u64) -> Result<RecHead, BlobError> {
+        let h = RecHead::read(&mut self.at(pos), pos)?;
+        if h.size() > b_end - pos {
+            return Err(BlobError::Corrupt { offset: pos });
+        }
+        Ok(h)
+    }
+
+    ///The blob at pos, given its head
+    fn body_at(&self, pos: u64, h: &RecHead) -> Result<Blob, BlobError> {
+        Blob::read_body(&mut self.at(pos + REC_HEAD), h, pos)
+    }
+
+    ///A reader of the file from pos that leaves its cursor alone
+    fn at(&self, pos: u64) -> ReadAt<'_> {
+        ReadAt::new(&self.file, pos)
+    }
+
+    fn write_gap(&mut self, pos: u64, vlen: u64) -> Result<(), BlobError> {
+        self.file.seek(SeekFrom::Start(pos))?;
+        RecHead::gap(vlen).out(&mut self.file)
+    }
+
+    ///The block a blob with this key belongs in
+    pub(super) fn block_of(&self, b: &Blob) -> u64 {
+        b.k_hash(self.hseed) % self.nblocks
     }
 
     fn b_start(&self, bucket: u64) -> u64 {
         CONT_SIZE + self.block_size * bucket
     }
 
-    pub fn get<K: Serialize>(&mut self, k: &K) -> Result<Blob, BlobError> {
-        let s_blob = Blob::from(k, &0_u64)?;
-        let bucket = s_blob.k_hash(self.hseed) % self.nblocks;
-        let b_start = self.b_start(bucket);
+    pub fn get<K: Serialize>(&self, k: &K) -> Result<Blob, BlobError> {
+        self.get_blob(&Blob::from(k, &0_u64)?)
+    }
+
+    ///Finds the stored blob with the same key as s_blob
+    pub(super) fn get_blob(&self, s_blob: &Blob) -> Result<Blob, BlobError> {
+        let bucket = self.block_of(s_blob);
         let b_end = self.b_start(bucket + 1);
-        let f = &mut self.file;
-        let mut pos = f.seek(SeekFrom::Start(b_start))?;
-        loop {
-            if pos >= b_end {
-                return Err(BlobError::NotFound);
-            }
-            let b = Blob::read(f)?;
-            if b.key_match(&s_blob) {
-                return Ok(b);
+        let mut pos = self.b_start(bucket);
+        while pos < b_end {
+            let h = self.head_at(pos, b_end)?;
+            if !h.is_gap() {
+                let b = self.body_at(pos, &h)?;
+                if b.key_match(s_blob) {
+                    return self.unspill(b)?.unpack();
+                }
             }
-            pos += b.len();
+            pos += h.size();
         }
+        Err(BlobError::NotFound)
     }
 
     pub fn new_or_open(fname: &str, block_size: u64, nblocks: u64) -> Result<Self, BlobError> {
@@ -148,46 +633,359 @@ impl BlobStore {
     }
 
     pub fn remove<K: Serialize>(&mut self, k: &K) -> Result<(), BlobError> {
-        let s_blob = Blob::from(k, &0_u64)?;
-        let bucket = s_blob.k_hash(self.hseed) % self.nblocks;
-        let b_start = self.b_start(bucket);
+        self.remove_blob(&Blob::from(k, &0_u64)?)
+    }
+
+    ///Removes the stored blob with the same key as s_blob, if there is one
+    pub(super) fn remove_blob(&mut self, s_blob: &Blob) -> Result<(), BlobError> {
+        self.atomic(&[self.block_of(s_blob)], |s| s.del_blob(s_blob).map(|_| ()))
+    }
+
+    ///Takes out the stored blob with the same key as s_blob, freeing its overflow pages,
+    ///and returns it as stored
+    fn del_blob(&mut self, s_blob: &Blob) -> Result<Option<Blob>, BlobError> {
+        let bucket = self.block_of(s_blob);
         let b_end = self.b_start(bucket + 1);
-        let f = &mut self.file;
-        let mut pos = f.seek(SeekFrom::Start(b_start))?;
+        let mut pos = self.b_start(bucket);
 
-        loop {
-            if pos >= b_end {
-                return Ok(());
+        while pos < b_end {
+            let h = self.head_at(pos, b_end)?;
+            if h.is_gap() {
+                pos += h.size();
+                continue;
             }
-            let b = Blob::read(f)?;
-            if b.key_match(&s_blob) {
-                let l = b.len();
+            let b = self.body_at(pos, &h)?;
+            if b.key_match(s_blob) {
+                let mut l = h.size();
                 //If next block is empty, we merge the two blobs
                 if pos + l < b_end {
-                    if read_u64(f)? == 0 {
-                        let nlen = read_u64(f)?;
-                        f.seek(SeekFrom::Start(pos))?;
-                        write_u64(f, 0)?;
-                        write_u64(f, l + nlen + 16)?;
-                        self.inc_elems(-1)?;
-                        return Ok(());
+                    let next = self.head_at(pos + l, b_end)?;
+                    if next.is_gap() {
+                        l += next.size();
+                    }
+                }
+                self.write_gap(pos, l - REC_HEAD)?;
+                if let Some(t) = self.auto_compact {
+                    if self.block_stat(bucket)?.fragmentation() > t {
+                        self.compact_block(bucket)?;
                     }
                 }
-                //otherwise we just leave this block empty
-                f.seek(SeekFrom::Start(pos))?;
-                write_u64(f, 0)?;
-                write_u64(f, l - 16)?;
                 self.inc_elems(-1)?;
-                return Ok(());
+                self.release(&b)?;
+                return Ok(Some(b));
+            }
+            pos += h.size();
+        }
+        Ok(None)
+    }
+
+    ///The blobs stored in block n, with stubs as they are
+    pub(super) fn block_blobs(&self, n: u64) -> Result<Vec<Blob>, BlobError> {
+        let b_end = self.b_start(n + 1);
+        let mut pos = self.b_start(n);
+        let mut res = Vec::new();
+        while pos < b_end {
+            let h = self.head_at(pos, b_end)?;
+            if !h.is_gap() {
+                res.push(self.body_at(pos, &h)?);
+            }
+            pos += h.size();
+        }
+        Ok(res)
+    }
+
+    ///Walks every block in file order
+    pub fn iter(&self) -> Blobs<'_> {
+        self.iter_from(0)
+    }
+
+    ///Walks the blocks from block n on
+    pub(super) fn iter_from(&self, n: u64) -> Blobs<'_> {
+        let (start, end) = (self.b_start(n), self.b_start(self.nblocks));
+        Blobs::new(self, start, end)
+    }
+
+    ///The blob at pos, or None if pos is a gap, with how many bytes either takes
+    pub(super) fn blob_at(&self, pos: u64) -> Result<(Option<Blob>, u64), BlobError> {
+        let b_end = self.b_start((pos - CONT_SIZE) / self.block_size + 1);
+        let h = self.head_at(pos, b_end)?;
+        if h.is_gap() {
+            return Ok((None, h.size()));
+        }
+        let b = self.body_at(pos, &h)?;
+        Ok((Some(self.unspill(b)?.unpack()?), h.size()))
+    }
+
+    ///Makes block n one empty gap
+    pub(super) fn clear_block(&mut self, n: u64) -> Result<(), BlobError> {
+        self.atomic(&[n], |s| {
+            let blobs = s.block_blobs(n)?;
+            s.write_gap(s.b_start(n), s.block_size - REC_HEAD)?;
+            s.inc_elems(-(blobs.len() as i32))?;
+            blobs.iter().try_for_each(|b| s.release(b))
+        })
+    }
+
+    ///Rewrites block n as blobs packed from its start, then one gap
+    fn pack_block(&mut self, n: u64, blobs: &[Blob]) -> Result<(), BlobError> {
+        let b_end = self.b_start(n + 1);
+        let total: u64 = blobs.iter().map(|b| b.len()).sum();
+        if total + REC_HEAD > self.block_size {
+            return Err(BlobError::NoRoom);
+        }
+        let mut pos = self.b_start(n);
+        self.file.seek(SeekFrom::Start(pos))?;
+        for b in blobs {
+            b.out(&mut self.file)?;
+            pos += b.len();
+        }
+        self.write_gap(pos, b_end - pos - REC_HEAD)
+    }
+
+    ///Packs every block that isn't already, so each has its free space as one gap.
+    ///Returns how many blocks were rewritten.
+    pub fn compact(&mut self) -> Result<u64, BlobError> {
+        let mut res = 0;
+        for n in 0..self.nblocks {
+            let st = self.block_stat(n)?;
+            if st.largest_gap < st.free {
+                self.atomic(&[n], |s| s.compact_block(n))?;
+                res += 1;
+            }
+        }
+        Ok(res)
+    }
+
+    ///Packs blocks as they need it, or stops with None.
+    ///A remove packs its block if that leaves its fragmentation above threshold,
+    ///and an insert packs its block if no gap fits the blob but packing would make one.
+    pub fn set_auto_compact(&mut self, threshold: Option<f64>) {
+        self.auto_compact = threshold;
+    }
+
+    pub fn block_stats(&self) -> Result<Vec<BlockStats>, BlobError> {
+        (0..self.nblocks).map(|n| self.block_stat(n)).collect()
+    }
+
+    fn block_stat(&self, n: u64) -> Result<BlockStats, BlobError> {
+        let b_end = self.b_start(n + 1);
+        let mut pos = self.b_start(n);
+        let mut res = BlockStats::default();
+        while pos < b_end {
+            let h = self.head_at(pos, b_end)?;
+            if h.is_gap() {
+                res.gaps += 1;
+                res.largest_gap = res.largest_gap.max(h.vlen);
+            } else {
+                res.blobs += 1;
+                res.used += h.size();
+            }
+            pos += h.size();
+        }
+        res.free = self.block_size - res.used - REC_HEAD;
+        Ok(res)
+    }
+
+    fn compact_block(&mut self, n: u64) -> Result<(), BlobError> {
+        let blobs = self.block_blobs(n)?;
+        self.pack_block(n, &blobs)
+    }
+
+    ///Checks every record in the store and the overflow pages, changing nothing
+    pub fn verify(&self) -> Result<VerifyReport, BlobError> {
+        let mut res = VerifyReport {
+            elems: self.elems,
+            ..Default::default()
+        };
+        let mut stubs = Vec::new();
+        for n in 0..self.nblocks {
+            let (good, bad) = self.scan_block(n)?;
+            res.live += good.len() as u64;
+            res.bad.extend(bad);
+            stubs.extend(good.into_iter().filter(|b| b.is_stub()));
+        }
+        let (lost, free_ok) = self.lost_pages(&stubs)?;
+        res.lost_pages = lost.len() as u64;
+        if !free_ok {
+            res.bad.push(0);
+        }
+        Ok(res)
+    }
+
+    ///Drops every record that fails its check, packing the rest of its block
+    ///so the free space is whole again, then sets the header's count to what is left.
+    ///Then frees every overflow page no value holds.
+    ///Returns what verify found beforehand.
+    pub fn repair(&mut self) -> Result<VerifyReport, BlobError> {
+        let mut res = VerifyReport {
+            elems: self.elems,
+            ..Default::default()
+        };
+        let mut stubs = Vec::new();
+        for n in 0..self.nblocks {
+            let (good, bad) = self.scan_block(n)?;
+            res.live += good.len() as u64;
+            if !bad.is_empty() {
+                self.atomic(&[n], |s| s.pack_block(n, &good))?;
+            }
+            res.bad.extend(bad);
+            stubs.extend(good.into_iter().filter(|b| b.is_stub()));
+        }
+        self.elems = res.live;
+        self.write_header()?;
+
+        let (lost, free_ok) = self.lost_pages(&stubs)?;
+        res.lost_pages = lost.len() as u64;
+        if !free_ok {
+            res.bad.push(0);
+        }
+        if !lost.is_empty() || !free_ok {
+            let pages = self.pages();
+            self.atomic(&[], |s| {
+                //onto the front of the free chain, or in its place if it is broken
+                let mut next = if free_ok { s.free_page } else { 0 };
+                for &p in lost.iter().rev() {
+                    s.write_at(pages.at(p), &next.to_le_bytes())?;
+                    next = p;
+                }
+                s.free_page = next;
+                s.write_header()
+            })?;
+        }
+        Ok(res)
+    }
+
+    ///The overflow pages neither in the chain of one of stubs nor free,
+    ///and whether the free chain is sound.  If it isn't, its pages count as lost.
+    fn lost_pages(&self, stubs: &[Blob]) -> Result<(Vec<u64>, bool), BlobError> {
+        let pages = self.pages();
+        let mut used = vec![false; pages.count(&mut self.at(0))? as usize];
+        for b in stubs {
+            let (first, _) = b.stub_target()?;
+            for p in pages.chain(&mut self.at(0), first)? {
+                used[p as usize - 1] = true;
+            }
+        }
+        //it ends at a page that is out of range or taken, if not at 0
+        let mut free = Vec::new();
+        let mut p = self.free_page;
+        while p != 0 && p as usize <= used.len() && !used[p as usize - 1] {
+            used[p as usize - 1] = true;
+            free.push(p);
+            p = pages.next(&mut self.at(0), p)?;
+        }
+        if p != 0 {
+            for f in free {
+                used[f as usize - 1] = false;
+            }
+        }
+        let lost = (1..=used.len() as u64)
+            .filter(|p| !used[*p as usize - 1])
+            .collect();
+        Ok((lost, p == 0))
+    }
+
+    ///The blobs in block n that pass their checks, and the offsets of those that don't.
+    ///After a head that fails, the walk tries each later offset
+    ///until one holds a head that passes.
+    fn scan_block(&self, n: u64) -> Result<(Vec<Blob>, Vec<u64>), BlobError> {
+        let b_end = self.b_start(n + 1);
+        let mut pos = self.b_start(n);
+        let (mut good, mut bad) = (Vec::new(), Vec::new());
+        while pos < b_end {
+            match self.head_at(pos, b_end) {
+                Ok(h) => {
+                    if !h.is_gap() {
+                        //a stub passes if the value in its pages does too
+                        let b = self
+                            .body_at(pos, &h)
+                            .and_then(|b| self.unspill(b.clone()).map(|_| b));
+                        match b {
+                            Ok(b) => good.push(b),
+                            Err(BlobError::Corrupt { .. }) => bad.push(pos),
+                            Err(e) => return Err(e),
+                        }
+                    }
+                    pos += h.size();
+                }
+                Err(BlobError::Corrupt { .. }) => {
+                    bad.push(pos);
+                    pos += 1;
+                    while pos + REC_HEAD <= b_end && self.head_at(pos, b_end).is_err() {
+                        pos += 1;
+                    }
+                    if pos + REC_HEAD > b_end {
+                        pos = b_end;
+                    }
+                }
+                Err(e) => return Err(e),
             }
-            pos = f.seek(SeekFrom::Start(pos + b.len()))?;
         }
+        Ok((good, bad))
+    }
+
+    ///A word kept after the last block for whatever wraps the store.
+    ///0 until set.
+    pub(super) fn spare(&self) -> Result<u64, BlobError> {
+        let end = self.b_start(self.nblocks);
+        if self.file.metadata()?.len() < end + 8 {
+            return Ok(0);
+        }
+        read_u64(&mut self.at(end))
+    }
+
+    pub(super) fn set_spare(&mut self, n: u64) -> Result<(), BlobError> {
+        self.file
+            .seek(SeekFrom::Start(self.b_start(self.nblocks)))?;
+        write_u64(&mut self.file, n)
+    }
+
+    ///The codec new values are packed with
+    pub fn codec(&self) -> Codec {
+        header::codec(self.flags).unwrap_or_default()
+    }
+
+    ///Packs values inserted from now on with c.
+    ///Values already stored stay as they are, and still unpack.
+    pub fn set_codec(&mut self, c: Codec) -> Result<(), BlobError> {
+        self.flags = header::with_codec(self.flags, c);
+        self.write_header()
+    }
+
+    pub fn tag(&self) -> u64 {
+        self.tag
+    }
+
+    pub fn set_tag(&mut self, tag: u64) -> Result<(), BlobError> {
+        self.tag = tag;
+        self.write_header()
+    }
+
+    pub(super) fn seed(&self) -> u64 {
+        self.hseed
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    pub fn nblocks(&self) -> u64 {
+        self.nblocks
+    }
+
+    pub fn len(&self) -> u64 {
+        self.elems
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elems == 0
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::storage::blob;
 
     #[test]
     fn test_create_file() {
@@ -218,4 +1016,391 @@ mod test {
         assert!(b3.get(&"green").is_err());
         assert!(b3.get(&"fish").is_ok());
     }
+
+    #[test]
+    fn test_iter() {
+        let fs = "test_data/iter_store";
+        std::fs::remove_file(fs).ok();
+        let mut bs = BlobStore::new(fs, 1000, 8).unwrap();
+        for x in 0..40 {
+            bs.insert(x, x * 10).unwrap();
+        }
+        for x in (0..40).step_by(4) {
+            bs.remove(&x).unwrap();
+        }
+        assert_eq!(bs.len(), 30);
+
+        let mut keys: Vec<i32> = bs.iter().keys().map(|k| k.unwrap()).collect();
+        keys.sort();
+        let want: Vec<i32> = (0..40).filter(|x| x % 4 != 0).collect();
+        assert_eq!(keys, want);
+        assert_eq!(bs.iter().count(), 30);
+        assert!(bs
+            .iter()
+            .pairs::<i32, i32>()
+            .all(|kv| kv.map(|(k, v)| v == k * 10).unwrap()));
+    }
+
+    fn offset_of(bs: &mut BlobStore, k: i32) -> u64 {
+        let s_blob = Blob::from(&k, &0_u64).unwrap();
+        let mut pos = bs.b_start(bs.block_of(&s_blob));
+        loop {
+            let (b, len) = bs.blob_at(pos).unwrap();
+            if b.is_some_and(|b| b.key_match(&s_blob)) {
+                return pos;
+            }
+            pos += len;
+        }
+    }
+
+    fn scribble(fs: &str, pos: u64) {
+        let mut f = OpenOptions::new().write(true).open(fs).unwrap();
+        f.seek(SeekFrom::Start(pos)).unwrap();
+        f.write_all(&[0xee]).unwrap();
+    }
+
+    #[test]
+    fn test_verify_repair() {
+        let fs = "test_data/verify_store";
+        std::fs::remove_file(fs).ok();
+        //a fixed seed, so no block gets more keys than it has room for
+        let mut bs = BlobStore::with_seed(fs, 1000, 4, 3).unwrap();
+        for x in 0..40 {
+            bs.insert(x, x * 10).unwrap();
+        }
+        assert!(bs.verify().unwrap().is_ok());
+
+        //a value byte, and the key length of another record
+        let body = offset_of(&mut bs, 7);
+        let head = offset_of(&mut bs, 12);
+        scribble(fs, body + REC_HEAD + 5);
+        scribble(fs, head + 1);
+        assert!(matches!(
+            bs.get(&7),
+            Err(BlobError::Corrupt { offset }) if offset == body
+        ));
+
+        let found = bs.verify().unwrap();
+        let mut want = vec![body, head];
+        want.sort();
+        assert_eq!(found.bad, want);
+        assert_eq!((found.live, found.elems), (38, 40));
+
+        assert_eq!(bs.repair().unwrap(), found);
+        assert!(bs.verify().unwrap().is_ok());
+        assert_eq!(bs.len(), 38);
+        for x in (0..40).filter(|x| *x != 7 && *x != 12) {
+            assert_eq!(bs.get(&x).unwrap().get_v::<i32>().unwrap(), x * 10);
+        }
+        assert!(matches!(bs.get(&7), Err(BlobError::NotFound)));
+        bs.insert(7, 70).unwrap();
+        assert_eq!(bs.get(&7).unwrap().get_v::<i32>().unwrap(), 70);
+
+        drop(bs);
+        scribble(fs, 20);
+        assert!(matches!(
+            BlobStore::open(fs),
+            Err(BlobError::Corrupt { offset: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_compact() {
+        let fs = "test_data/compact_store";
+        std::fs::remove_file(fs).ok();
+        let mut bs = BlobStore::new(fs, 1000, 1).unwrap();
+        let small = "s".repeat(20);
+        for x in 0..15 {
+            bs.insert(x, &small).unwrap();
+        }
+        for x in (0..15).step_by(2) {
+            bs.remove(&x).unwrap();
+        }
+        let st = bs.block_stats().unwrap()[0];
+        assert_eq!((st.blobs, st.gaps), (7, 8));
+        assert!(st.fragmentation() > 0.6);
+
+        //enough room in all, but not in any one gap
+        let big = "b".repeat(300);
+        assert!(matches!(bs.insert(100, &big), Err(BlobError::NoRoom)));
+        assert_eq!(bs.compact().unwrap(), 1);
+        assert_eq!(bs.compact().unwrap(), 0);
+        let packed = bs.block_stats().unwrap()[0];
+        assert_eq!((packed.blobs, packed.gaps), (7, 1));
+        assert_eq!(packed.largest_gap, st.free);
+        assert_eq!(packed.fragmentation(), 0.0);
+        bs.insert(100, &big).unwrap();
+        for x in (1..15).step_by(2) {
+            assert_eq!(bs.get(&x).unwrap().get_v::<String>().unwrap(), small);
+        }
+        assert!(bs.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_auto_compact() {
+        let fs = "test_data/auto_compact_store";
+        std::fs::remove_file(fs).ok();
+        let mut bs = BlobStore::new(fs, 1000, 1).unwrap();
+        bs.set_auto_compact(Some(0.9));
+        let small = "s".repeat(20);
+        for x in 0..15 {
+            bs.insert(x, &small).unwrap();
+        }
+        for x in (0..15).step_by(2) {
+            bs.remove(&x).unwrap();
+        }
+        //under the threshold, so left alone until an insert needs the room
+        assert_eq!(bs.block_stats().unwrap()[0].gaps, 8);
+        bs.insert(100, "b".repeat(300)).unwrap();
+        assert_eq!(bs.block_stats().unwrap()[0].gaps, 1);
+
+        bs.set_auto_compact(Some(0.1));
+        bs.remove(&1).unwrap();
+        assert_eq!(bs.block_stats().unwrap()[0].gaps, 1);
+        assert_eq!(bs.len(), 7);
+        assert!(bs.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_overflow() {
+        let fs = "test_data/overflow_store";
+        std::fs::remove_file(fs).ok();
+        //a fixed seed, so the small keys fit their small blocks
+        let mut bs = BlobStore::with_seed(fs, 256, 4, 5).unwrap();
+        let big: String = (0..3000)
+            .map(|x| char::from(b'a' + (x % 26) as u8))
+            .collect();
+        for x in 0..10 {
+            bs.insert(x, x).unwrap();
+        }
+        bs.insert(100, &big).unwrap();
+        bs.insert(101, &big[..1000]).unwrap();
+        assert_eq!(bs.len(), 12);
+        assert_eq!(bs.get(&100).unwrap().get_v::<String>().unwrap(), big);
+        let flen = bs.file.metadata().unwrap().len();
+
+        //freed pages are used again before the file grows,
+        //though a replaced value's only once its replacement is in
+        bs.remove(&100).unwrap();
+        assert!(bs.get(&100).is_err());
+        bs.insert(102, &big[..2000]).unwrap();
+        bs.insert(101, &big[1000..1900]).unwrap();
+        assert_eq!(bs.file.metadata().unwrap().len(), flen);
+        drop(bs);
+
+        let mut bs = BlobStore::open(fs).unwrap();
+        assert_eq!(
+            bs.get(&101).unwrap().get_v::<String>().unwrap(),
+            &big[1000..1900]
+        );
+        let mut big_vals: Vec<String> = bs
+            .iter()
+            .pairs::<i32, String>()
+            .filter_map(|kv| kv.ok().map(|(_, v)| v))
+            .collect();
+        big_vals.sort();
+        assert_eq!(big_vals, [&big[..2000], &big[1000..1900]]);
+        assert!(bs.verify().unwrap().is_ok());
+        //the stub itself has to fit
+        assert!(matches!(
+            bs.insert("k".repeat(300), 1),
+            Err(BlobError::TooBig(_))
+        ));
+
+        //a bad page fails the get, and repair drops its value and frees its pages
+        let first = bs.pages().at(bs.get_raw(&101).stub_target().unwrap().0);
+        bs.file.seek(SeekFrom::Start(first + 30)).unwrap();
+        bs.file.write_all(b"?").unwrap();
+        assert!(matches!(bs.get(&101), Err(BlobError::Corrupt { .. })));
+        let report = bs.verify().unwrap();
+        assert_eq!(report.bad.len(), 1);
+        assert_eq!(bs.repair().unwrap().bad, report.bad);
+        let after = bs.verify().unwrap();
+        assert!(after.is_ok());
+        assert_eq!(after.live, 11);
+
+        //pages freed without the header taking them up, as a crash without the WAL can do,
+        //are lost until repair
+        bs.remove(&102).unwrap();
+        bs.free_page = 0;
+        bs.write_header().unwrap();
+        let lost = bs.verify().unwrap().lost_pages;
+        assert!(lost > 0);
+        assert_eq!(bs.repair().unwrap().lost_pages, lost);
+        assert!(bs.verify().unwrap().is_ok());
+        let flen = bs.file.metadata().unwrap().len();
+        bs.insert(104, &big[..2000]).unwrap();
+        assert_eq!(bs.file.metadata().unwrap().len(), flen);
+    }
+
+    impl BlobStore {
+        ///The blob under k as stored, stub and all
+        fn get_raw(&self, k: &i32) -> Blob {
+            let s_blob = Blob::from(k, &0_u64).unwrap();
+            let blobs = self.block_blobs(self.block_of(&s_blob)).unwrap();
+            blobs.into_iter().find(|b| b.key_match(&s_blob)).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_compression() {
+        let fs = "test_data/packed_store";
+        let text: String = (0..400)
+            .map(|x| format!("record {} of the test text, ", x % 7))
+            .collect();
+        let used =
+            |bs: &BlobStore| -> u64 { bs.block_stats().unwrap().iter().map(|s| s.used).sum() };
+        let mut sizes = Vec::new();
+        for c in [Codec::Raw, Codec::Deflate, Codec::Huffman] {
+            std::fs::remove_file(fs).ok();
+            let mut bs = BlobStore::with_seed(fs, 4000, 8, 6).unwrap();
+            bs.set_codec(c).unwrap();
+            for x in 0..8 {
+                bs.insert(x, &text[..1000 + x * 100]).unwrap();
+            }
+            //too small to gain anything, so kept raw
+            bs.insert("small", 1).unwrap();
+            assert_eq!(bs.get(&"small").unwrap().get_v::<i32>().unwrap(), 1);
+            sizes.push(used(&bs));
+            drop(bs);
+
+            let bs = BlobStore::open(fs).unwrap();
+            assert_eq!(bs.codec(), c);
+            let vals: Vec<(usize, String)> = bs
+                .iter()
+                .pairs::<usize, String>()
+                .filter_map(|kv| kv.ok())
+                .collect();
+            assert_eq!(vals.len(), 8);
+            for (k, v) in vals {
+                assert_eq!(v, &text[..1000 + k * 100]);
+            }
+            assert!(bs.verify().unwrap().is_ok());
+        }
+        assert!(sizes[1] < sizes[0] / 4);
+        assert!(sizes[2] < sizes[0] * 2 / 3);
+
+        //values packed before a switch still read after it,
+        //and one too big for a block raw fits once packed
+        let mut bs = BlobStore::open(fs).unwrap();
+        bs.set_codec(Codec::Deflate).unwrap();
+        bs.insert(20, &text).unwrap();
+        assert_eq!(
+            bs.get(&3_usize).unwrap().get_v::<String>().unwrap(),
+            &text[..1300]
+        );
+        assert_eq!(bs.get(&20).unwrap().get_v::<String>().unwrap(), text);
+        let mut tx = bs.transaction();
+        tx.insert(21, &text[..2000]).unwrap();
+        tx.commit().unwrap();
+        assert_eq!(
+            bs.get(&21).unwrap().get_v::<String>().unwrap(),
+            &text[..2000]
+        );
+        assert!(bs.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_readers_and_lock() {
+        let fs = "test_data/shared_store";
+        std::fs::remove_file(fs).ok();
+        let mut bs = BlobStore::new(fs, 1000, 16).unwrap();
+        assert!(matches!(BlobStore::open(fs), Err(BlobError::Locked)));
+        for x in 0..50 {
+            bs.insert(x, x * 2).unwrap();
+        }
+
+        let bs = std::sync::RwLock::new(bs);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for x in 0..50 {
+                        let v: i32 = bs.read().unwrap().get(&x).unwrap().get_v().unwrap();
+                        assert_eq!(v, x * 2);
+                    }
+                });
+            }
+            s.spawn(|| {
+                for x in 50..100 {
+                    bs.write().unwrap().insert(x, x * 2).unwrap();
+                }
+            });
+        });
+        let bs = bs.into_inner().unwrap();
+        assert_eq!(bs.len(), 100);
+        assert_eq!(bs.iter().count(), 100);
+
+        //the lock goes with the store
+        drop(bs);
+        assert!(BlobStore::open(fs).unwrap().verify().unwrap().is_ok());
+    }
+
+    ///Writes a store as the format before versions did, each block's records then a gap
+    fn write_legacy(fs: &str, seed: u64, block_size: u64, blocks: &[Vec<(Vec<u8>, Vec<u8>)>]) {
+        let mut out = Vec::new();
+        let elems = blocks.iter().map(|b| b.len() as u64).sum();
+        for x in [seed, block_size, blocks.len() as u64, elems] {
+            write_u64(&mut out, x).unwrap();
+        }
+        for (n, recs) in blocks.iter().enumerate() {
+            for (k, v) in recs {
+                write_u64(&mut out, k.len() as u64).unwrap();
+                write_u64(&mut out, v.len() as u64).unwrap();
+                out.extend(k);
+                out.extend(v);
+            }
+            let b_end = 32 + block_size as usize * (n + 1);
+            let gap = (b_end - out.len() - 16) as u64;
+            write_u64(&mut out, 0).unwrap();
+            write_u64(&mut out, gap).unwrap();
+            out.resize(b_end, 0);
+        }
+        std::fs::write(fs, out).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_legacy() {
+        let fs = "test_data/legacy_store";
+        let (seed, nblocks) = (77, 3);
+        //four small records fill a block of 200 with the old 16 byte heads,
+        //and the big one only fits a block to itself
+        let mut blocks = vec![Vec::new(); nblocks];
+        let mut want = Vec::new();
+        for x in 0..1000 {
+            let (k, v) = (x, format!("value {:>6}", x));
+            let kb = bincode::serialize(&k).unwrap();
+            let n = blob::key_hash(&kb, seed) % nblocks as u64;
+            if n < 2 && blocks[n as usize].len() < 4 {
+                blocks[n as usize].push((kb, bincode::serialize(&v).unwrap()));
+                want.push((k, v));
+            }
+        }
+        let big = "b".repeat(150);
+        let kb = (1000..)
+            .map(|x: i32| bincode::serialize(&x).unwrap())
+            .find(|kb| blob::key_hash(kb, seed) % nblocks as u64 == 2)
+            .unwrap();
+        let big_key: i32 = bincode::deserialize(&kb).unwrap();
+        blocks[2].push((kb, bincode::serialize(&big).unwrap()));
+        want.push((big_key, big));
+        write_legacy(fs, seed, 200, &blocks);
+
+        assert!(matches!(BlobStore::open(fs), Err(BlobError::NotAStore)));
+        BlobStore::upgrade(fs).unwrap();
+        let bs = BlobStore::open(fs).unwrap();
+        assert_eq!(bs.len(), 9);
+        assert_eq!(bs.block_size(), 200);
+        assert!(bs.nblocks() > 3 && bs.nblocks().is_multiple_of(3));
+        for (k, v) in &want {
+            assert_eq!(&bs.get(k).unwrap().get_v::<String>().unwrap(), v);
+        }
+        assert!(bs.verify().unwrap().is_ok());
+        drop(bs);
+        //already current
+        BlobStore::upgrade(fs).unwrap();
+        assert_eq!(BlobStore::open(fs).unwrap().len(), 9);
+
+        std::fs::write(fs, [1u8; 20]).unwrap();
+        assert!(matches!(BlobStore::upgrade(fs), Err(BlobError::NotAStore)));
+    }
 }
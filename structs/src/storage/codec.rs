@@ -0,0 +1,94 @@
+use crate::{BlobError, HuffEncodedString};
+
+///How a store packs the values it is given, kept in its header's flags.
+///
+///A packed value starts with its codec's id, so it unpacks whatever the store's
+///codec is by then, and a value packing doesn't shrink is stored as it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    #[default]
+    Raw,
+    ///DEFLATE, from miniz_oxide
+    Deflate,
+    ///The crate's HuffEncodedString, over the bytes as chars.
+    ///Tree and all, so it only pays on longer values.
+    Huffman,
+}
+
+impl Codec {
+    pub fn id(self) -> u8 {
+        match self {
+            Codec::Raw => 0,
+            Codec::Deflate => 1,
+            Codec::Huffman => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Codec::Raw),
+            1 => Some(Codec::Deflate),
+            2 => Some(Codec::Huffman),
+            _ => None,
+        }
+    }
+
+    ///v packed, or None if that is no smaller
+    pub fn pack(self, v: &[u8]) -> Option<Vec<u8>> {
+        let mut res = vec![self.id()];
+        match self {
+            Codec::Raw => return None,
+            Codec::Deflate => res.extend(miniz_oxide::deflate::compress_to_vec(v, 6)),
+            Codec::Huffman => {
+                let s: String = v.iter().map(|&b| b as char).collect();
+                res.extend(HuffEncodedString::encode(&s).to_bytes()?);
+            }
+        }
+        (res.len() < v.len()).then_some(res)
+    }
+
+    ///The value a packed one came from
+    pub fn unpack(v: &[u8]) -> Result<Vec<u8>, BlobError> {
+        let (&id, body) = v.split_first().ok_or(BlobError::Unpack)?;
+        match Self::from_id(id).ok_or(BlobError::Unpack)? {
+            Codec::Raw => Ok(body.to_vec()),
+            Codec::Deflate => {
+                miniz_oxide::inflate::decompress_to_vec(body).map_err(|_| BlobError::Unpack)
+            }
+            Codec::Huffman => {
+                let s = HuffEncodedString::from_bytes(body).and_then(|h| h.decode());
+                s.ok_or(BlobError::Unpack)?
+                    .chars()
+                    .map(|c| u8::try_from(c).map_err(|_| BlobError::Unpack))
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let text = "the quick brown fox jumps over the lazy dog. ".repeat(20);
+        for c in [Codec::Deflate, Codec::Huffman] {
+            let p = c.pack(text.as_bytes()).unwrap();
+            assert!(
+                p.len() < text.len() * 4 / 5,
+                "{:?} packed to {}",
+                c,
+                p.len()
+            );
+            assert_eq!(Codec::unpack(&p).unwrap(), text.as_bytes());
+            //too short to be worth it
+            assert!(c.pack(b"abc").is_none());
+        }
+        assert!(Codec::Raw.pack(text.as_bytes()).is_none());
+        assert!(matches!(Codec::unpack(&[9, 1, 2]), Err(BlobError::Unpack)));
+        let mut deep = vec![0; 1 << 21];
+        deep[0] = Codec::Huffman.id();
+        assert!(matches!(Codec::unpack(&deep), Err(BlobError::Unpack)));
+    }
+}
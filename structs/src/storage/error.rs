@@ -6,6 +6,24 @@ pub enum BlobError {
     TooBig(u64),
     #[error("Not Found")]
     NotFound,
+    #[error("Corrupt record at offset {offset}")]
+    Corrupt { offset: u64 },
+    #[error("Not a BlobStore.  A store from before format versions needs BlobStore::upgrade")]
+    NotAStore,
+    #[error("Unsupported Version.  File has format version {found}, this build reads {supported}")]
+    Version { found: u64, supported: u64 },
+    #[error("Unsupported Flags {0:#x}")]
+    Flags(u64),
+    #[error("Unpack Error.  A packed value would not unpack")]
+    Unpack,
+    #[error("Locked.  Another store has the file open, and one of the two would write")]
+    Locked,
+    #[error("Unrecovered.  A crash left a change to undo, which opening the store to write does")]
+    Unrecovered,
+    #[error(
+        "Wrong Types.  Store was made for types with fingerprint {found:#x}, not {expected:#x}"
+    )]
+    WrongTypes { expected: u64, found: u64 },
     #[error("Bincode Error: {0}")]
     Bincode(bincode::Error),
     #[error("IO Error: {0}")]
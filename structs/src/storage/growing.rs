@@ -0,0 +1,428 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{Blob, BlobError, BlobStore};
+
+///How many times one insert may start a growth before giving up with NoRoom.
+///Each doubles the blocks, so only keys whose hashes all but match get this far.
+const GROWTH_TRIES: usize = 8;
+
+///A BlobStore that doubles its blocks when one fills, instead of returning NoRoom.
+///
+///Like HMap, it moves the blocks across to the bigger store one per insert.
+///The bigger store lives in `<fname>.grow` until the last block is moved,
+///then is renamed over fname, so fname always holds a whole store.
+///Both use the same seed, so block n of main only ever goes to blocks n and
+///n + nblocks of grow, and a key's home is main until its block has moved.
+pub struct GrowingBlobStore {
+    fname: String,
+    main: BlobStore,
+    grow: Option<BlobStore>,
+    ///Blocks of main already moved to grow, kept in grow's spare word
+    n_moved: u64,
+}
+
+fn grow_name(fname: &str) -> String {
+    format!("{}.grow", fname)
+}
+
+impl GrowingBlobStore {
+    pub fn new(fname: &str, block_size: u64, nblocks: u64) -> Result<Self, BlobError> {
+        let main = BlobStore::new(fname, block_size, nblocks)?;
+        //left by a store that used to have this name
+        std::fs::remove_file(grow_name(fname)).ok();
+        Ok(GrowingBlobStore {
+            fname: fname.to_string(),
+            main,
+            grow: None,
+            n_moved: 0,
+        })
+    }
+
+    ///Opens fname, picking up any migration that was running when it was closed
+    pub fn open(fname: &str) -> Result<Self, BlobError> {
+        let main = BlobStore::open(fname)?;
+        let gname = grow_name(fname);
+        let (grow, n_moved) = if Path::new(&gname).exists() {
+            let grow = BlobStore::open(&gname)?;
+            let n_moved = grow.spare()?;
+            (Some(grow), n_moved)
+        } else {
+            (None, 0)
+        };
+        let mut res = GrowingBlobStore {
+            fname: fname.to_string(),
+            main,
+            grow,
+            n_moved,
+        };
+        res.recover()?;
+        Ok(res)
+    }
+
+    pub fn new_or_open(fname: &str, block_size: u64, nblocks: u64) -> Result<Self, BlobError> {
+        Self::new(fname, block_size, nblocks).or_else(|_| Self::open(fname))
+    }
+
+    pub fn insert<K: Serialize, V: Serialize>(&mut self, k: K, v: V) -> Result<(), BlobError> {
+        let blob = Blob::from(&k, &v)?;
+        self.move_block()?;
+        for _ in 0..GROWTH_TRIES {
+            //the old value goes only if the new one is in
+            match self.store_for(&blob).replace_blob(&blob) {
+                Err(BlobError::NoRoom) => self.grow_past(&blob)?,
+                r => return r,
+            }
+        }
+        Err(BlobError::NoRoom)
+    }
+
+    pub fn get<K: Serialize>(&self, k: &K) -> Result<Blob, BlobError> {
+        let s_blob = Blob::from(k, &0_u64)?;
+        let moved = self.main.block_of(&s_blob) < self.n_moved;
+        match &self.grow {
+            Some(g) if moved => g.get_blob(&s_blob),
+            _ => self.main.get_blob(&s_blob),
+        }
+    }
+
+    pub fn remove<K: Serialize>(&mut self, k: &K) -> Result<(), BlobError> {
+        let s_blob = Blob::from(k, &0_u64)?;
+        self.move_block()?;
+        self.store_for(&s_blob).remove_blob(&s_blob)
+    }
+
+    ///Every live blob, from the blocks of main not yet moved, then from grow
+    pub fn iter(&self) -> impl Iterator<Item = Result<Blob, BlobError>> + '_ {
+        let n = match self.grow {
+            Some(_) => self.n_moved,
+            None => 0,
+        };
+        let rest = self.grow.iter().flat_map(|g| g.iter());
+        self.main.iter_from(n).chain(rest)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.main.len() + self.grow.as_ref().map_or(0, |g| g.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    ///Turns the write-ahead log on or off for the store and any it is growing into,
+    ///see BlobStore::set_wal
+    pub fn set_wal(&mut self, on: bool) -> Result<(), BlobError> {
+        self.main.set_wal(on)?;
+        match &mut self.grow {
+            Some(g) => g.set_wal(on),
+            None => Ok(()),
+        }
+    }
+
+    ///The block count the store has, or is moving to
+    pub fn nblocks(&self) -> u64 {
+        self.grow.as_ref().unwrap_or(&self.main).nblocks()
+    }
+
+    pub fn is_migrating(&self) -> bool {
+        self.grow.is_some()
+    }
+
+    ///The store holding b's key: grow if its block has moved, else main
+    fn store_for(&mut self, b: &Blob) -> &mut BlobStore {
+        let moved = self.main.block_of(b) < self.n_moved;
+        match &mut self.grow {
+            Some(g) if moved => g,
+            _ => &mut self.main,
+        }
+    }
+
+    ///Called when b's block has no room for it.
+    ///Moves blocks until b's is in grow, first finishing the migration and
+    ///starting another if b's block has already moved.
+    fn grow_past(&mut self, b: &Blob) -> Result<(), BlobError> {
+        if self.grow.is_some() && self.main.block_of(b) < self.n_moved {
+            while self.grow.is_some() {
+                self.move_block()?;
+            }
+        }
+        if self.grow.is_none() {
+            let mut grow = BlobStore::with_seed(
+                &grow_name(&self.fname),
+                self.main.block_size(),
+                self.main.nblocks() * 2,
+                self.main.seed(),
+            )?;
+            grow.set_tag(self.main.tag())?;
+            grow.set_codec(self.main.codec())?;
+            grow.set_wal(self.main.has_wal())?;
+            self.grow = Some(grow);
+            self.n_moved = 0;
+        }
+        while self.grow.is_some() && self.main.block_of(b) >= self.n_moved {
+            self.move_block()?;
+        }
+        Ok(())
+    }
+
+    ///Moves one block from main to grow, then swaps the files once they all have.
+    ///A block is copied before it is counted as moved, and cleared after,
+    ///so a crash part way leaves every entry readable, though until open
+    ///tidies up with recover, some may be in both files.
+    fn move_block(&mut self) -> Result<(), BlobError> {
+        let Some(grow) = &mut self.grow else {
+            return Ok(());
+        };
+        let n = self.n_moved;
+        for b in self.main.block_blobs(n)? {
+            //grow has overflow pages of its own
+            let b = self.main.unspill(b)?;
+            grow.insert_blob(&b)?;
+        }
+        grow.set_spare(n + 1)?;
+        self.main.clear_block(n)?;
+        self.n_moved += 1;
+
+        if self.n_moved == self.main.nblocks() {
+            self.swap()?;
+        }
+        Ok(())
+    }
+
+    ///Makes grow the store, once every block has moved
+    fn swap(&mut self) -> Result<(), BlobError> {
+        //rename is atomic, so fname is either the old store or the new one
+        std::fs::rename(grow_name(&self.fname), &self.fname)?;
+        self.main = self.grow.take().expect("only called while migrating");
+        self.main.renamed(&self.fname)?;
+        self.n_moved = 0;
+        Ok(())
+    }
+
+    ///Finishes or undoes a move_block a crash cut short, so no entry is in both files.
+    ///A block counted as moved but not yet cleared from main is cleared,
+    ///and copies grow has of the next block, from before the crash, are dropped
+    ///to be made again when it moves.
+    fn recover(&mut self) -> Result<(), BlobError> {
+        let Some(grow) = &mut self.grow else {
+            return Ok(());
+        };
+        let (n, nblocks) = (self.n_moved, self.main.nblocks());
+        if n > 0 && !self.main.block_blobs(n - 1)?.is_empty() {
+            self.main.clear_block(n - 1)?;
+        }
+        if n == nblocks {
+            return self.swap();
+        }
+        //grow's only blocks main's block n goes to, which nothing else writes before it moves
+        for g in [n, n + nblocks] {
+            if !grow.block_blobs(g)?.is_empty() {
+                grow.clear_block(g)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fresh(fname: &str) {
+        for f in [fname.to_string(), grow_name(fname)] {
+            std::fs::remove_file(format!("{}.wal", f)).ok();
+            std::fs::remove_file(f).ok();
+        }
+    }
+
+    #[test]
+    fn test_grows_instead_of_no_room() {
+        let fs = "test_data/growing_store";
+        fresh(fs);
+        let mut gs = GrowingBlobStore::new(fs, 256, 2).unwrap();
+        let big = "b".repeat(1000);
+        gs.insert("big", &big).unwrap();
+        let mut saw_migration = false;
+        for x in 0..500 {
+            gs.insert(x, format!("value {}", x)).unwrap();
+            saw_migration |= gs.is_migrating();
+            //readable at every step, whichever file it is in
+            assert_eq!(
+                gs.get(&(x / 2)).unwrap().get_v::<String>().unwrap(),
+                format!("value {}", x / 2)
+            );
+        }
+        assert!(saw_migration);
+        assert!(gs.nblocks() >= 64);
+        assert_eq!(gs.len(), 501);
+        assert_eq!(gs.get(&"big").unwrap().get_v::<String>().unwrap(), big);
+        gs.remove(&"big").unwrap();
+
+        for x in (0..500).step_by(2) {
+            gs.remove(&x).unwrap();
+        }
+        gs.insert(1, "replaced").unwrap();
+        assert_eq!(gs.len(), 250);
+        assert!(gs.get(&10).is_err());
+        assert_eq!(gs.get(&1).unwrap().get_v::<String>().unwrap(), "replaced");
+        assert_eq!(
+            gs.get(&499).unwrap().get_v::<String>().unwrap(),
+            "value 499"
+        );
+    }
+
+    #[test]
+    fn test_reopen_mid_migration() {
+        let fs = "test_data/growing_reopen";
+        fresh(fs);
+        let mut gs = GrowingBlobStore::new(fs, 256, 4).unwrap();
+        let mut x = 0;
+        while x < 50 || !gs.is_migrating() {
+            gs.insert(x, x * 3).unwrap();
+            x += 1;
+        }
+        let moved = gs.n_moved;
+        drop(gs);
+
+        let mut gs = GrowingBlobStore::open(fs).unwrap();
+        assert!(gs.is_migrating());
+        assert_eq!(gs.n_moved, moved);
+        for y in x..x + 300 {
+            gs.insert(y, y * 3).unwrap();
+        }
+        assert!(!Path::new(&grow_name(fs)).exists() || gs.is_migrating());
+        drop(gs);
+
+        let gs = GrowingBlobStore::open(fs).unwrap();
+        assert_eq!(gs.len(), x as u64 + 300);
+        assert_eq!(gs.iter().count() as u64, gs.len());
+        for y in 0..x + 300 {
+            assert_eq!(gs.get(&y).unwrap().get_v::<i32>().unwrap(), y * 3);
+        }
+    }
+
+    #[test]
+    fn test_remove_steps_migration() {
+        let fs = "test_data/growing_remove";
+        fresh(fs);
+        let mut gs = GrowingBlobStore::new(fs, 256, 4).unwrap();
+        let mut x = 0;
+        while x < 50 || !gs.is_migrating() {
+            gs.insert(x, x * 3).unwrap();
+            x += 1;
+        }
+        //removes alone finish the migration
+        let mut y = 0;
+        while gs.is_migrating() {
+            let moved = gs.n_moved;
+            gs.remove(&y).unwrap();
+            assert!(gs.n_moved > moved || !gs.is_migrating());
+            y += 1;
+        }
+        assert!(!Path::new(&grow_name(fs)).exists());
+        assert_eq!(gs.len(), (x - y) as u64);
+        for z in y..x {
+            assert_eq!(gs.get(&z).unwrap().get_v::<i32>().unwrap(), z * 3);
+        }
+    }
+
+    #[test]
+    fn test_too_big() {
+        let fs = "test_data/growing_too_big";
+        fresh(fs);
+        let mut gs = GrowingBlobStore::new(fs, 64, 2).unwrap();
+        assert!(matches!(
+            gs.insert(1, vec![0u8; 64]),
+            Err(BlobError::TooBig(_))
+        ));
+        assert!(gs.is_empty());
+    }
+
+    #[test]
+    fn test_too_big_keeps_old_value() {
+        let fs = "test_data/growing_too_big_overwrite";
+        fresh(fs);
+        let mut gs = GrowingBlobStore::new(fs, 64, 2).unwrap();
+        gs.insert(1, 5u8).unwrap();
+        assert!(matches!(
+            gs.insert(1, vec![0u8; 64]),
+            Err(BlobError::TooBig(_))
+        ));
+        assert_eq!(gs.get(&1).unwrap().get_v::<u8>().unwrap(), 5);
+        assert_eq!(gs.len(), 1);
+    }
+
+    #[test]
+    fn test_crash_mid_move() {
+        let fs = "test_data/growing_crash_move";
+        fresh(fs);
+        let mut gs = GrowingBlobStore::new(fs, 256, 4).unwrap();
+        let mut x = 0;
+        //with two blocks still to move
+        while x < 50 || !gs.is_migrating() || gs.n_moved + 2 > gs.main.nblocks() {
+            gs.insert(x, x * 3).unwrap();
+            x += 1;
+        }
+        //copy the next two blocks, counting only the first as moved and clearing neither,
+        //as a crash after set_spare, then one while copying, would leave them
+        let n = gs.n_moved;
+        let grow = gs.grow.as_mut().unwrap();
+        for m in [n, n + 1] {
+            for b in gs.main.block_blobs(m).unwrap() {
+                grow.insert_blob(&gs.main.unspill(b).unwrap()).unwrap();
+            }
+        }
+        grow.set_spare(n + 1).unwrap();
+        drop(gs);
+
+        let gs = GrowingBlobStore::open(fs).unwrap();
+        assert_eq!(gs.n_moved, n + 1);
+        assert_eq!(gs.len(), x as u64);
+        assert_eq!(gs.iter().count() as u64, x as u64);
+        for y in 0..x {
+            assert_eq!(gs.get(&y).unwrap().get_v::<i32>().unwrap(), y * 3);
+        }
+    }
+
+    #[test]
+    fn test_wal_covers_grow() {
+        let fs = "test_data/growing_wal";
+        for steps in 0..40 {
+            fresh(fs);
+            let mut gs = GrowingBlobStore::new(fs, 256, 4).unwrap();
+            gs.set_wal(true).unwrap();
+            let mut x = 0;
+            while x < 50 || !gs.is_migrating() {
+                gs.insert(x, x * 3).unwrap();
+                x += 1;
+            }
+            assert!(gs.grow.as_ref().unwrap().has_wal());
+            //a key whose block has moved, so the insert writes to grow
+            let k = (0..x)
+                .find(|k| gs.main.block_of(&Blob::from(k, &0_u64).unwrap()) < gs.n_moved)
+                .unwrap();
+            gs.grow.as_mut().unwrap().crash_after(steps);
+            let crashed = gs.insert(k, -1).is_err();
+            drop(gs);
+
+            let mut gs = GrowingBlobStore::open(fs).unwrap();
+            let v: i32 = gs.get(&k).unwrap().get_v().unwrap();
+            assert_eq!(v, if crashed { k * 3 } else { -1 });
+            assert_eq!(gs.len(), x as u64);
+            if !crashed {
+                //the store grown into keeps the log once it takes over fname
+                while gs.is_migrating() {
+                    gs.move_block().unwrap();
+                }
+                assert!(gs.main.has_wal());
+                drop(gs);
+                assert!(GrowingBlobStore::open(fs).unwrap().main.has_wal());
+                assert!(!Path::new(&format!("{}.wal", grow_name(fs))).exists());
+                return;
+            }
+        }
+        panic!("never finished");
+    }
+}
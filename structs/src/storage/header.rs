@@ -0,0 +1,167 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::BlobError;
+
+use super::{
+    blob::{read_u64, write_u64},
+    codec::Codec,
+};
+
+///The first word of every store file, "BlobStr" and a 0 byte
+pub const MAGIC: u64 = u64::from_le_bytes(*b"BlobStr\0");
+
+///The file format this build writes.
+///Before 1 there was no magic: a 32 byte header, 16 byte record heads and no checks.
+pub const VERSION: u64 = 1;
+
+///Records carry CRC32 checks.  Every version 1 store has it.
+pub const CHECKSUMS: u64 = 1;
+
+///The id of the Codec new values are packed with, in bits 8 to 15
+const CODEC_SHIFT: u64 = 8;
+const CODEC_BITS: u64 = 0xff << CODEC_SHIFT;
+
+///Flags this build understands
+const KNOWN_FLAGS: u64 = CHECKSUMS | CODEC_BITS;
+
+///The codec flags name, if this build knows it
+pub fn codec(flags: u64) -> Option<Codec> {
+    Codec::from_id(((flags & CODEC_BITS) >> CODEC_SHIFT) as u8)
+}
+
+pub fn with_codec(flags: u64, c: Codec) -> u64 {
+    flags & !CODEC_BITS | (c.id() as u64) << CODEC_SHIFT
+}
+
+///Bytes the header takes: magic, version, flags, hseed, block_size, nblocks,
+///elems, tag, free_page, then a CRC32 of those
+pub const SIZE: u64 = 80;
+
+///What a store's header holds besides its magic and version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub flags: u64,
+    pub hseed: u64,
+    pub block_size: u64,
+    pub nblocks: u64,
+    pub elems: u64,
+    pub tag: u64,
+    pub free_page: u64,
+}
+
+impl Header {
+    ///Reads the header at the start of r.
+    ///The magic and version are checked before anything else,
+    ///so a newer format fails with Version whatever the rest looks like.
+    pub fn read<R: Read + Seek>(r: &mut R) -> Result<Self, BlobError> {
+        let mut h = [0u8; SIZE as usize];
+        r.seek(SeekFrom::Start(0))?;
+        if r.read_exact(&mut h).is_err() || read_u64(&mut &h[..])? != MAGIC {
+            return Err(BlobError::NotAStore);
+        }
+        let version = read_u64(&mut &h[8..])?;
+        if version != VERSION {
+            return Err(BlobError::Version {
+                found: version,
+                supported: VERSION,
+            });
+        }
+        let body = SIZE as usize - 8;
+        if crc32fast::hash(&h[..body]) as u64 != read_u64(&mut &h[body..])? {
+            return Err(BlobError::Corrupt { offset: 0 });
+        }
+        let mut f = [0u64; 7];
+        let mut rd = &h[16..body];
+        for x in f.iter_mut() {
+            *x = read_u64(&mut rd)?;
+        }
+        let [flags, hseed, block_size, nblocks, elems, tag, free_page] = f;
+        if flags & !KNOWN_FLAGS != 0 || flags & CHECKSUMS == 0 || codec(flags).is_none() {
+            return Err(BlobError::Flags(flags));
+        }
+        Ok(Header {
+            flags,
+            hseed,
+            block_size,
+            nblocks,
+            elems,
+            tag,
+            free_page,
+        })
+    }
+
+    pub fn bytes(&self) -> Result<Vec<u8>, BlobError> {
+        let mut res = Vec::with_capacity(SIZE as usize);
+        for x in [
+            MAGIC,
+            VERSION,
+            self.flags,
+            self.hseed,
+            self.block_size,
+            self.nblocks,
+            self.elems,
+            self.tag,
+            self.free_page,
+        ] {
+            write_u64(&mut res, x)?;
+        }
+        let check = crc32fast::hash(&res) as u64;
+        write_u64(&mut res, check)?;
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_header_checks() {
+        let h = Header {
+            flags: CHECKSUMS,
+            hseed: 5,
+            block_size: 1000,
+            nblocks: 4,
+            elems: 3,
+            tag: 0,
+            free_page: 0,
+        };
+        let b = h.bytes().unwrap();
+        assert_eq!(b.len() as u64, SIZE);
+        assert_eq!(Header::read(&mut Cursor::new(&b)).unwrap(), h);
+
+        let with = |at: usize, word: u64| {
+            let mut b = b.clone();
+            b[at..at + 8].copy_from_slice(&word.to_le_bytes());
+            Header::read(&mut Cursor::new(b))
+        };
+        assert!(matches!(with(0, 7), Err(BlobError::NotAStore)));
+        assert!(matches!(
+            with(8, 2),
+            Err(BlobError::Version {
+                found: 2,
+                supported: 1
+            })
+        ));
+        assert!(matches!(with(48, 9), Err(BlobError::Corrupt { offset: 0 })));
+        assert!(matches!(
+            Header::read(&mut Cursor::new(&b[..40])),
+            Err(BlobError::NotAStore)
+        ));
+
+        for flags in [3, 0x7701] {
+            let odd = Header { flags, ..h }.bytes().unwrap();
+            assert!(matches!(
+                Header::read(&mut Cursor::new(odd)),
+                Err(BlobError::Flags(f)) if f == flags
+            ));
+        }
+        let packed = Header {
+            flags: with_codec(CHECKSUMS, Codec::Deflate),
+            ..h
+        };
+        let back = Header::read(&mut Cursor::new(packed.bytes().unwrap())).unwrap();
+        assert_eq!(codec(back.flags), Some(Codec::Deflate));
+    }
+}
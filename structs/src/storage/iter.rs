@@ -0,0 +1,56 @@
+use serde::de::DeserializeOwned;
+
+use crate::{Blob, BlobError, BlobStore};
+
+///Every live blob in a store, block by block, from BlobStore::iter.
+///Gaps are skipped.  After an error the iterator ends.
+pub struct Blobs<'a> {
+    store: &'a BlobStore,
+    pos: u64,
+    end: u64,
+}
+
+impl<'a> Blobs<'a> {
+    pub(super) fn new(store: &'a BlobStore, pos: u64, end: u64) -> Self {
+        Blobs { store, pos, end }
+    }
+
+    ///Deserializes each key
+    pub fn keys<K: DeserializeOwned>(self) -> impl Iterator<Item = Result<K, BlobError>> + 'a {
+        self.map(|b| b?.get_k())
+    }
+
+    ///Deserializes each key and value
+    pub fn pairs<K, V>(self) -> impl Iterator<Item = Result<(K, V), BlobError>> + 'a
+    where
+        K: DeserializeOwned,
+        V: DeserializeOwned,
+    {
+        self.map(|b| {
+            let b = b?;
+            Ok((b.get_k()?, b.get_v()?))
+        })
+    }
+}
+
+impl<'a> Iterator for Blobs<'a> {
+    type Item = Result<Blob, BlobError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pos < self.end {
+            match self.store.blob_at(self.pos) {
+                Ok((b, len)) => {
+                    self.pos += len;
+                    if b.is_some() {
+                        return b.map(Ok);
+                    }
+                }
+                Err(e) => {
+                    self.pos = self.end;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
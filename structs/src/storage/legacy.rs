@@ -0,0 +1,68 @@
+use crate::{Blob, BlobError};
+
+use super::blob::read_u64;
+
+///The header of a store from before format versions: hseed, block_size, nblocks, elems
+const SIZE: u64 = 32;
+
+///Its record heads: klen then vlen, klen 0 for a gap, with no check
+const REC_HEAD: u64 = 16;
+
+///What a store from before format versions held
+pub(super) struct Legacy {
+    pub hseed: u64,
+    pub block_size: u64,
+    pub nblocks: u64,
+    pub blobs: Vec<Blob>,
+}
+
+///Reads a whole store in the format from before versions,
+///failing with NotAStore if buf can't be one.
+///That format's removes could leave a gap claiming bytes past its block's end,
+///so a gap running off the end just ends the block.
+pub(super) fn read(buf: &[u8]) -> Result<Legacy, BlobError> {
+    let word = |at: u64| read_u64(&mut buf.get(at as usize..).unwrap_or_default());
+    if (buf.len() as u64) < SIZE {
+        return Err(BlobError::NotAStore);
+    }
+    let (hseed, block_size, nblocks) = (word(0)?, word(8)?, word(16)?);
+    let fits = block_size
+        .checked_mul(nblocks)
+        .and_then(|b| b.checked_add(SIZE))
+        .is_some_and(|end| end <= buf.len() as u64);
+    if block_size < REC_HEAD || nblocks == 0 || !fits {
+        return Err(BlobError::NotAStore);
+    }
+
+    let mut blobs = Vec::new();
+    for n in 0..nblocks {
+        let b_end = SIZE + block_size * (n + 1);
+        let mut pos = SIZE + block_size * n;
+        while pos + REC_HEAD <= b_end {
+            let (klen, vlen) = (word(pos)?, word(pos + 8)?);
+            let size = klen
+                .checked_add(vlen)
+                .and_then(|s| s.checked_add(REC_HEAD))
+                .filter(|s| *s <= b_end - pos);
+            match size {
+                Some(size) => {
+                    if klen != 0 {
+                        let k = (pos + REC_HEAD) as usize;
+                        let v = k + klen as usize;
+                        let end = v + vlen as usize;
+                        blobs.push(Blob::from_bytes(buf[k..v].to_vec(), buf[v..end].to_vec()));
+                    }
+                    pos += size;
+                }
+                None if klen == 0 => break,
+                None => return Err(BlobError::Corrupt { offset: pos }),
+            }
+        }
+    }
+    Ok(Legacy {
+        hseed,
+        block_size,
+        nblocks,
+        blobs,
+    })
+}
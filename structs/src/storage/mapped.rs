@@ -0,0 +1,217 @@
+use std::{borrow::Cow, fs::File, io::Cursor};
+
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+
+use crate::BlobError;
+
+use super::{
+    blob::{key_hash, RecHead, REC_HEAD},
+    blobstore::{check_recovered, lock_shared, CONT_SIZE},
+    codec::Codec,
+    header::Header,
+    overflow::Pages,
+};
+
+///A read only view of a BlobStore's file through a memory map.
+///
+///A lookup slices the map instead of seeking and reading,
+///and hands back the key and value where they lie in it.
+///It holds the file's lock shared, like ReadOnlyBlobStore, so while it is open
+///no BlobStore can open the file to write, and the map never changes under a borrow.
+pub struct MappedBlobStore {
+    //dropped before the file, which gives up the lock when closed
+    map: Mmap,
+    ///Kept open only for its lock
+    _file: File,
+    hseed: u64,
+    block_size: u64,
+    nblocks: u64,
+}
+
+///A key and value borrowed from a MappedBlobStore.
+///The value is copied only if it was packed or split over overflow pages.
+pub struct BlobRef<'a> {
+    k: &'a [u8],
+    v: Cow<'a, [u8]>,
+}
+
+impl<'a> BlobRef<'a> {
+    pub fn get_k<'b, K: Deserialize<'b>>(&'b self) -> Result<K, BlobError> {
+        Ok(bincode::deserialize(self.k)?)
+    }
+
+    ///Deserializes the value, so a &str or &[u8] in it borrows from the map
+    pub fn get_v<'b, V: Deserialize<'b>>(&'b self) -> Result<V, BlobError> {
+        Ok(bincode::deserialize(&self.v)?)
+    }
+
+    ///The serialized key
+    pub fn key(&self) -> &'a [u8] {
+        self.k
+    }
+
+    ///The serialized value
+    pub fn value(&self) -> &[u8] {
+        &self.v
+    }
+}
+
+///Maps file, whose shared lock the caller holds for as long as the map lives
+fn map(file: &File) -> Result<Mmap, BlobError> {
+    //Safety: while the shared lock is held no BlobStore writes to or truncates the file,
+    //so the map, and whatever borrows from it, stays as it is.
+    //A process writing the file without taking its lock is not guarded against.
+    Ok(unsafe { Mmap::map(file)? })
+}
+
+impl MappedBlobStore {
+    ///Fails with Locked while a BlobStore has the file open to write,
+    ///and with Unrecovered if a crash left a change in the WAL, as ReadOnlyBlobStore does
+    pub fn open(fname: &str) -> Result<Self, BlobError> {
+        let file = File::open(fname)?;
+        lock_shared(&file)?;
+        check_recovered(fname)?;
+        let map = map(&file)?;
+        let h = Header::read(&mut Cursor::new(&map[..]))?;
+        Ok(MappedBlobStore {
+            map,
+            _file: file,
+            hseed: h.hseed,
+            block_size: h.block_size,
+            nblocks: h.nblocks,
+        })
+    }
+
+    pub fn get<K: Serialize>(&self, k: &K) -> Result<BlobRef<'_>, BlobError> {
+        let key = bincode::serialize(k)?;
+        let bucket = key_hash(&key, self.hseed) % self.nblocks;
+        let b_end = self.b_start(bucket + 1);
+        let mut pos = self.b_start(bucket);
+        while pos < b_end {
+            let h = RecHead::read(&mut self.slice(pos, REC_HEAD)?, pos)?;
+            if h.size() > b_end - pos {
+                return Err(BlobError::Corrupt { offset: pos });
+            }
+            if !h.is_gap() {
+                let k = self.slice(pos + REC_HEAD, h.klen)?;
+                let v = self.slice(pos + REC_HEAD + h.klen, h.vlen)?;
+                if !h.checks(k, v) {
+                    return Err(BlobError::Corrupt { offset: pos });
+                }
+                if k == key {
+                    let mut v = match h.stub {
+                        true => Cow::Owned(self.overflow(v)?),
+                        false => Cow::Borrowed(v),
+                    };
+                    if h.packed {
+                        v = Cow::Owned(Codec::unpack(&v)?);
+                    }
+                    return Ok(BlobRef { k, v });
+                }
+            }
+            pos += h.size();
+        }
+        Err(BlobError::NotFound)
+    }
+
+    ///The value a stub's value points to
+    fn overflow(&self, stub: &[u8]) -> Result<Vec<u8>, BlobError> {
+        let (first, len): (u64, u64) = bincode::deserialize(stub)?;
+        let pages = Pages::after(self.b_start(self.nblocks), self.block_size);
+        pages.read(&mut Cursor::new(&self.map[..]), first, len)
+    }
+
+    fn slice(&self, pos: u64, len: u64) -> Result<&[u8], BlobError> {
+        let end = pos.checked_add(len).filter(|e| *e <= self.map.len() as u64);
+        match end {
+            Some(end) => Ok(&self.map[pos as usize..end as usize]),
+            None => Err(BlobError::Corrupt { offset: pos }),
+        }
+    }
+
+    fn b_start(&self, bucket: u64) -> u64 {
+        CONT_SIZE + self.block_size * bucket
+    }
+
+    pub fn len(&self) -> u64 {
+        Header::read(&mut Cursor::new(&self.map[..])).map_or(0, |h| h.elems)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{BlobStore, ReadOnlyBlobStore};
+
+    #[test]
+    fn test_mapped_get() {
+        let fs = "test_data/mapped_store";
+        std::fs::remove_file(fs).ok();
+        let mut bs = BlobStore::new(fs, 1000, 4).unwrap();
+        for x in 0..20 {
+            bs.insert(x, format!("value {}", x)).unwrap();
+        }
+        let big = "b".repeat(3000);
+        bs.insert(100, &big).unwrap();
+        //packed values come back unpacked
+        bs.set_codec(Codec::Deflate).unwrap();
+        bs.insert(102, "d".repeat(2000)).unwrap();
+        assert!(matches!(MappedBlobStore::open(fs), Err(BlobError::Locked)));
+        drop(bs);
+
+        let ms = MappedBlobStore::open(fs).unwrap();
+        assert_eq!(ms.len(), 22);
+        for x in 0..20 {
+            let b = ms.get(&x).unwrap();
+            //borrowed straight from the map
+            let v: &str = b.get_v().unwrap();
+            assert_eq!(v, format!("value {}", x));
+            assert_eq!(b.get_k::<i32>().unwrap(), x);
+        }
+        assert_eq!(ms.get(&100).unwrap().get_v::<&str>().unwrap(), big);
+        assert!(matches!(ms.get(&20), Err(BlobError::NotFound)));
+        assert_eq!(
+            ms.get(&102).unwrap().get_v::<&str>().unwrap(),
+            "d".repeat(2000)
+        );
+
+        //readers share the file, but no writer can open it under the map
+        let ms2 = MappedBlobStore::open(fs).unwrap();
+        let rs = ReadOnlyBlobStore::open(fs).unwrap();
+        assert!(matches!(BlobStore::open(fs), Err(BlobError::Locked)));
+        assert_eq!(ms2.get(&5).unwrap().value(), rs.get(&5).unwrap().value());
+        drop((ms, ms2, rs));
+        BlobStore::open(fs).unwrap().insert(5, "changed").unwrap();
+        let ms = MappedBlobStore::open(fs).unwrap();
+        assert_eq!(ms.get(&5).unwrap().get_v::<&str>().unwrap(), "changed");
+    }
+
+    #[test]
+    fn test_mapped_waits_for_recovery() {
+        let fs = "test_data/mapped_unrecovered";
+        std::fs::remove_file(fs).ok();
+        std::fs::remove_file(format!("{}.wal", fs)).ok();
+        let mut bs = BlobStore::new(fs, 1000, 4).unwrap();
+        for x in 0..20 {
+            bs.insert(x, x * 3).unwrap();
+        }
+        bs.set_wal(true).unwrap();
+        bs.crash_after(1);
+        assert!(bs.insert(4, 0).is_err());
+        drop(bs);
+        //the map would show the half written block
+        assert!(matches!(
+            MappedBlobStore::open(fs),
+            Err(BlobError::Unrecovered)
+        ));
+        drop(BlobStore::open(fs).unwrap());
+        let ms = MappedBlobStore::open(fs).unwrap();
+        assert_eq!(ms.get(&4).unwrap().get_v::<i32>().unwrap(), 12);
+        assert_eq!(ms.len(), 20);
+    }
+}
@@ -1,7 +1,29 @@
 pub mod blob;
+pub mod blobmap;
 pub mod blobstore;
+pub mod codec;
 pub mod error;
+pub mod growing;
+mod header;
+pub mod iter;
+mod legacy;
+#[cfg(feature = "mmap")]
+pub mod mapped;
+mod overflow;
+pub mod read_only;
+#[cfg(test)]
+mod test_util;
+pub mod transaction;
+mod wal;
 
 pub use blob::Blob;
-pub use blobstore::BlobStore;
+pub use blobmap::{BlobMap, Schema};
+pub use blobstore::{BlobStore, BlockStats, VerifyReport};
+pub use codec::Codec;
 pub use error::BlobError;
+pub use growing::GrowingBlobStore;
+pub use iter::Blobs;
+#[cfg(feature = "mmap")]
+pub use mapped::{BlobRef, MappedBlobStore};
+pub use read_only::ReadOnlyBlobStore;
+pub use transaction::Transaction;
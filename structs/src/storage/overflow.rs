@@ -0,0 +1,163 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::BlobError;
+
+use super::blob::{read_u64, write_u64};
+
+///Bytes before each page's data: the next page in its chain, how many bytes it uses,
+///then a CRC32 of those and the bytes used
+const PAGE_HEAD: u64 = 24;
+
+///Bytes to write at an offset, for the store to make
+pub(super) type Writes = Vec<(u64, Vec<u8>)>;
+
+///Overflow pages at the end of a store's file, for values too big for a block.
+///
+///Pages are numbered from 1, so 0 can end a chain.
+///A value too big for its block is split over a chain of pages,
+///and the block keeps a stub holding the chain's first page and the value's length.
+///Free pages form one more chain, whose first page the store's header keeps,
+///and new pages are only added at the end of the file when it is empty.
+///
+///Nothing here writes to the file.  The writes a change needs are handed back,
+///so the store can log the bytes they cover first.
+pub(super) struct Pages {
+    ///Where page 1 starts
+    base: u64,
+    size: u64,
+}
+
+fn page_check(next: u64, used: u64, data: &[u8]) -> u64 {
+    let mut h = crc32fast::Hasher::new();
+    h.update(&next.to_le_bytes());
+    h.update(&used.to_le_bytes());
+    h.update(data);
+    h.finalize() as u64
+}
+
+impl Pages {
+    pub fn at(&self, p: u64) -> u64 {
+        self.base + (p - 1) * self.size
+    }
+
+    ///The pages of a store whose blocks end at blocks_end, after its spare word
+    pub fn after(blocks_end: u64, size: u64) -> Self {
+        Pages {
+            base: blocks_end + 8,
+            size,
+        }
+    }
+
+    ///How many pages the file has room for
+    pub fn count<F: Seek>(&self, f: &mut F) -> Result<u64, BlobError> {
+        let len = f.seek(SeekFrom::End(0))?;
+        Ok(len.saturating_sub(self.base) / self.size)
+    }
+
+    ///The page after p in its chain, failing with Corrupt if p isn't a page
+    pub fn next<F: Read + Seek>(&self, f: &mut F, p: u64) -> Result<u64, BlobError> {
+        if p == 0 || p > self.count(f)? {
+            return Err(BlobError::Corrupt { offset: self.base });
+        }
+        f.seek(SeekFrom::Start(self.at(p)))?;
+        read_u64(f)
+    }
+
+    ///The writes putting data in a new chain, taking pages from the free chain first,
+    ///and the new chain's first page.
+    ///Each write is a whole page, so the next page added starts where the file ends.
+    pub fn write<F: Read + Seek>(
+        &self,
+        f: &mut F,
+        free: &mut u64,
+        data: &[u8],
+    ) -> Result<(u64, Writes), BlobError> {
+        let chunks: Vec<&[u8]> = data.chunks((self.size - PAGE_HEAD) as usize).collect();
+        let mut pages = Vec::with_capacity(chunks.len());
+        let mut count = self.count(f)?;
+        for _ in &chunks {
+            if *free != 0 {
+                pages.push(*free);
+                *free = self.next(f, *free)?;
+            } else {
+                count += 1;
+                pages.push(count);
+            }
+        }
+        let mut res = Vec::with_capacity(chunks.len());
+        for (i, c) in chunks.iter().enumerate() {
+            let next = pages.get(i + 1).copied().unwrap_or(0);
+            let used = c.len() as u64;
+            let mut page = Vec::with_capacity(self.size as usize);
+            write_u64(&mut page, next)?;
+            write_u64(&mut page, used)?;
+            write_u64(&mut page, page_check(next, used, c))?;
+            page.extend_from_slice(c);
+            page.resize(self.size as usize, 0);
+            res.push((self.at(pages[i]), page));
+        }
+        Ok((pages.first().copied().unwrap_or(0), res))
+    }
+
+    ///Reads back len bytes from the chain starting at first,
+    ///failing with Corrupt if a page fails its check
+    pub fn read<F: Read + Seek>(
+        &self,
+        f: &mut F,
+        first: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, BlobError> {
+        let mut res = Vec::with_capacity(len as usize);
+        let mut p = first;
+        while (res.len() as u64) < len {
+            let next = self.next(f, p)?;
+            let used = read_u64(f)?;
+            let check = read_u64(f)?;
+            if used > self.size - PAGE_HEAD || res.len() as u64 + used > len {
+                return Err(BlobError::Corrupt { offset: self.at(p) });
+            }
+            let start = res.len();
+            res.resize(start + used as usize, 0);
+            f.read_exact(&mut res[start..])?;
+            if page_check(next, used, &res[start..]) != check {
+                return Err(BlobError::Corrupt { offset: self.at(p) });
+            }
+            p = next;
+        }
+        Ok(res)
+    }
+
+    ///The pages of the chain from first, in order
+    pub fn chain<F: Read + Seek>(&self, f: &mut F, first: u64) -> Result<Vec<u64>, BlobError> {
+        let mut res = Vec::new();
+        let mut p = first;
+        //a loop in the chain would otherwise never end
+        for _ in 0..self.count(f)? {
+            if p == 0 {
+                return Ok(res);
+            }
+            res.push(p);
+            p = self.next(f, p)?;
+        }
+        match p {
+            0 => Ok(res),
+            _ => Err(BlobError::Corrupt { offset: self.base }),
+        }
+    }
+
+    ///The write putting the chain from first on the front of the free chain
+    pub fn free<F: Read + Seek>(
+        &self,
+        f: &mut F,
+        free: &mut u64,
+        first: u64,
+    ) -> Result<(u64, Vec<u8>), BlobError> {
+        let last = *self
+            .chain(f, first)?
+            .last()
+            .ok_or(BlobError::Corrupt { offset: self.base })?;
+        let res = (self.at(last), free.to_le_bytes().to_vec());
+        *free = first;
+        Ok(res)
+    }
+}
@@ -0,0 +1,107 @@
+use serde::Serialize;
+
+use crate::{Blob, BlobError, BlobStore, Blobs, BlockStats, Codec, VerifyReport};
+
+///A BlobStore opened only to read.
+///
+///It takes the file's lock shared, so any number of these, in this process
+///or others, can have the file open at once, while a BlobStore opening it
+///to write fails with Locked, as does opening one of these while a writer has it.
+pub struct ReadOnlyBlobStore {
+    store: BlobStore,
+}
+
+impl ReadOnlyBlobStore {
+    ///Fails with Unrecovered if a crash left a change in the WAL,
+    ///until a BlobStore opened to write has undone it
+    pub fn open(fname: &str) -> Result<Self, BlobError> {
+        Ok(ReadOnlyBlobStore {
+            store: BlobStore::open_shared(fname)?,
+        })
+    }
+
+    pub fn get<K: Serialize>(&self, k: &K) -> Result<Blob, BlobError> {
+        self.store.get(k)
+    }
+
+    ///Walks every block in file order
+    pub fn iter(&self) -> Blobs<'_> {
+        self.store.iter()
+    }
+
+    pub fn verify(&self) -> Result<VerifyReport, BlobError> {
+        self.store.verify()
+    }
+
+    pub fn block_stats(&self) -> Result<Vec<BlockStats>, BlobError> {
+        self.store.block_stats()
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.store.codec()
+    }
+
+    pub fn tag(&self) -> u64 {
+        self.store.tag()
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.store.block_size()
+    }
+
+    pub fn nblocks(&self) -> u64 {
+        self.store.nblocks()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.store.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.store.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_readers_share() {
+        let fs = "test_data/read_only_store";
+        std::fs::remove_file(fs).ok();
+        std::fs::remove_file(format!("{}.wal", fs)).ok();
+        let mut bs = BlobStore::new(fs, 1000, 8).unwrap();
+        for x in 0..30 {
+            bs.insert(x, x * 5).unwrap();
+        }
+        assert!(matches!(
+            ReadOnlyBlobStore::open(fs),
+            Err(BlobError::Locked)
+        ));
+        drop(bs);
+
+        let r1 = ReadOnlyBlobStore::open(fs).unwrap();
+        let r2 = ReadOnlyBlobStore::open(fs).unwrap();
+        assert!(matches!(BlobStore::open(fs), Err(BlobError::Locked)));
+        assert_eq!(r1.get(&7).unwrap().get_v::<i32>().unwrap(), 35);
+        assert_eq!(r2.iter().count(), 30);
+        assert!(r2.verify().unwrap().is_ok());
+        drop((r1, r2));
+
+        //a change a crash cut short waits for a writer to undo it
+        let mut bs = BlobStore::open(fs).unwrap();
+        bs.set_wal(true).unwrap();
+        bs.crash_after(1);
+        assert!(bs.insert(3, 0).is_err());
+        drop(bs);
+        assert!(matches!(
+            ReadOnlyBlobStore::open(fs),
+            Err(BlobError::Unrecovered)
+        ));
+        drop(BlobStore::open(fs).unwrap());
+        let r = ReadOnlyBlobStore::open(fs).unwrap();
+        assert_eq!(r.get(&3).unwrap().get_v::<i32>().unwrap(), 15);
+        assert_eq!(r.len(), 30);
+    }
+}
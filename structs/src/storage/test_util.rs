@@ -0,0 +1,14 @@
+use super::BlobStore;
+
+///A store at fs holding keys 0 to 19, key x with value(x), its files cleared first
+pub(super) fn fresh(fs: &str, wal: bool, value: fn(i32) -> i32) -> BlobStore {
+    std::fs::remove_file(fs).ok();
+    std::fs::remove_file(format!("{}.wal", fs)).ok();
+    //a fixed seed, so no block gets more keys than it has room for
+    let mut bs = BlobStore::with_seed(fs, 400, 4, 7).unwrap();
+    bs.set_wal(wal).unwrap();
+    for x in 0..20 {
+        bs.insert(x, value(x)).unwrap();
+    }
+    bs
+}
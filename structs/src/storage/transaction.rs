@@ -0,0 +1,141 @@
+use serde::Serialize;
+
+use crate::{Blob, BlobError, BlobStore};
+
+///A change waiting in a Transaction
+pub(super) enum Op {
+    Put(Blob),
+    ///Holds a blob with just the key
+    Remove(Blob),
+}
+
+impl Op {
+    pub fn key(&self) -> &Blob {
+        match self {
+            Op::Put(b) | Op::Remove(b) => b,
+        }
+    }
+}
+
+///Inserts and removes buffered against a BlobStore, from BlobStore::transaction.
+///
+///Nothing reaches the store until commit, which writes them all or none,
+///even if the process dies part way with the WAL on.
+///Dropping it uncommitted throws them away.
+pub struct Transaction<'a> {
+    store: &'a mut BlobStore,
+    ///At most one per key, the latest
+    ops: Vec<Op>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(super) fn new(store: &'a mut BlobStore) -> Self {
+        Transaction {
+            store,
+            ops: Vec::new(),
+        }
+    }
+
+    pub fn insert<K: Serialize, V: Serialize>(&mut self, k: K, v: V) -> Result<(), BlobError> {
+        self.push(Op::Put(Blob::from(&k, &v)?));
+        Ok(())
+    }
+
+    pub fn remove<K: Serialize>(&mut self, k: &K) -> Result<(), BlobError> {
+        self.push(Op::Remove(Blob::from(k, &0_u64)?));
+        Ok(())
+    }
+
+    ///The value k has in the store once this commits
+    pub fn get<K: Serialize>(&self, k: &K) -> Result<Blob, BlobError> {
+        let s_blob = Blob::from(k, &0_u64)?;
+        match self.ops.iter().find(|op| op.key().key_match(&s_blob)) {
+            Some(Op::Put(b)) => Ok(b.clone()),
+            Some(Op::Remove(_)) => Err(BlobError::NotFound),
+            None => self.store.get_blob(&s_blob),
+        }
+    }
+
+    ///Writes every change, or on an error none of them
+    pub fn commit(self) -> Result<(), BlobError> {
+        self.store.apply(&self.ops)
+    }
+
+    fn push(&mut self, op: Op) {
+        self.ops.retain(|o| !o.key().key_match(op.key()));
+        self.ops.push(op);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::test_util::fresh;
+
+    ///Moves 10 from a to b, and puts a note under a new key
+    fn transfer(bs: &mut BlobStore, a: i32, b: i32) -> Result<(), BlobError> {
+        let mut tx = bs.transaction();
+        let from: i32 = tx.get(&a)?.get_v()?;
+        let to: i32 = tx.get(&b)?.get_v()?;
+        tx.insert(a, from - 10)?;
+        tx.insert(b, to + 10)?;
+        tx.insert("note", format!("{} to {}", a, b))?;
+        //sees its own writes
+        assert_eq!(tx.get(&a)?.get_v::<i32>()?, from - 10);
+        tx.commit()
+    }
+
+    fn total(bs: &BlobStore) -> i32 {
+        (0..20)
+            .map(|x| bs.get(&x).unwrap().get_v::<i32>().unwrap())
+            .sum()
+    }
+
+    #[test]
+    fn test_commit_and_drop() {
+        let fs = "test_data/tx_store";
+        let mut bs = fresh(fs, false, |_| 100);
+        transfer(&mut bs, 3, 17).unwrap();
+        assert_eq!(bs.get(&3).unwrap().get_v::<i32>().unwrap(), 90);
+        assert_eq!(bs.get(&17).unwrap().get_v::<i32>().unwrap(), 110);
+        assert_eq!(bs.len(), 21);
+
+        let mut tx = bs.transaction();
+        tx.remove(&3).unwrap();
+        tx.insert(4, 0).unwrap();
+        assert!(matches!(tx.get(&3), Err(BlobError::NotFound)));
+        drop(tx);
+        assert_eq!(bs.get(&3).unwrap().get_v::<i32>().unwrap(), 90);
+        assert_eq!(bs.get(&4).unwrap().get_v::<i32>().unwrap(), 100);
+
+        //one write with no room fails the rest with it
+        let mut tx = bs.transaction();
+        tx.remove(&5).unwrap();
+        tx.insert(6, "x".repeat(340)).unwrap();
+        assert!(matches!(tx.commit(), Err(BlobError::NoRoom)));
+        assert_eq!(total(&bs), 2000);
+        assert_eq!(bs.len(), 21);
+        assert!(bs.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_crash_mid_commit() {
+        let fs = "test_data/tx_crash";
+        for steps in 0..20 {
+            let mut bs = fresh(fs, true, |_| 100);
+            bs.crash_after(steps);
+            let crashed = transfer(&mut bs, 2, 9).is_err();
+            drop(bs);
+
+            let bs = BlobStore::open(fs).unwrap();
+            assert_eq!(total(&bs), 2000);
+            let moved = bs.get(&2).unwrap().get_v::<i32>().unwrap() == 90;
+            assert_eq!(moved, !crashed);
+            assert_eq!(bs.get(&"note").is_ok(), !crashed);
+            if !crashed {
+                return;
+            }
+        }
+        panic!("never committed");
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+};
+
+use crate::BlobError;
+
+use super::blob::{read_u64, write_u64};
+
+///An undo log for a BlobStore.
+///
+///Before each change, the old bytes of every range it may write are appended
+///as one record and synced, and a range it only finds it must write part way
+///gets a record of its own before it is written.
+///Once the change itself is synced the log is emptied.
+///So the whole records found on open belong to a change that may have been cut short,
+///and writing their bytes back, last first, then cutting the file back to
+///the length it had undoes it.  A torn record never reached the store.
+///
+///A record is the file's length before the change, a count of ranges,
+///then each range's offset, length and bytes, then a CRC32 of all of that.
+pub(super) struct Wal {
+    file: File,
+}
+
+///Offsets in the store, each with the bytes that were there
+pub(super) type Ranges = Vec<(u64, Vec<u8>)>;
+
+pub(super) fn encode(flen: u64, ranges: &[(u64, Vec<u8>)]) -> Result<Vec<u8>, BlobError> {
+    let mut res = Vec::new();
+    write_u64(&mut res, flen)?;
+    write_u64(&mut res, ranges.len() as u64)?;
+    for (pos, b) in ranges {
+        write_u64(&mut res, *pos)?;
+        write_u64(&mut res, b.len() as u64)?;
+        res.extend_from_slice(b);
+    }
+    let check = crc32fast::hash(&res) as u64;
+    write_u64(&mut res, check)?;
+    Ok(res)
+}
+
+///The file length and ranges of the whole record at the start of buf,
+///moving buf past it
+fn decode(buf: &mut &[u8]) -> Option<(u64, Ranges)> {
+    let mut r = *buf;
+    let flen = read_u64(&mut r).ok()?;
+    let n = read_u64(&mut r).ok()?;
+    let mut res = Vec::new();
+    for _ in 0..n {
+        let pos = read_u64(&mut r).ok()?;
+        let len = read_u64(&mut r).ok()? as usize;
+        if len > r.len() {
+            return None;
+        }
+        let (b, rest) = r.split_at(len);
+        res.push((pos, b.to_vec()));
+        r = rest;
+    }
+    let body = buf.len() - r.len();
+    let check = read_u64(&mut r).ok()?;
+    if crc32fast::hash(&buf[..body]) as u64 != check {
+        return None;
+    }
+    *buf = r;
+    Some((flen, res))
+}
+
+impl Wal {
+    pub fn open(fname: &str) -> Result<Self, BlobError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(fname)?;
+        Ok(Wal { file })
+    }
+
+    pub fn append(&mut self, b: &[u8]) -> Result<(), BlobError> {
+        self.file.seek(SeekFrom::End(0))?;
+        Ok(self.file.write_all(b)?)
+    }
+
+    pub fn sync(&mut self) -> Result<(), BlobError> {
+        Ok(self.file.sync_data()?)
+    }
+
+    ///Empties the log, once the change it covers is safely in the store
+    pub fn checkpoint(&mut self) -> Result<(), BlobError> {
+        self.file.set_len(0)?;
+        self.sync()
+    }
+
+    ///The file length to go back to and the ranges to write back, in the order logged,
+    ///to undo an unfinished change, if there is one
+    pub fn unfinished(&mut self) -> Result<Option<(u64, Ranges)>, BlobError> {
+        let mut buf = Vec::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut buf)?;
+        Ok(unfinished(&buf))
+    }
+}
+
+///What Wal::unfinished gives for a log holding buf
+pub(super) fn unfinished(buf: &[u8]) -> Option<(u64, Ranges)> {
+    let mut r = buf;
+    let (flen, mut res) = decode(&mut r)?;
+    while let Some((_, more)) = decode(&mut r) {
+        res.extend(more);
+    }
+    Some((flen, res))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::test_util::fresh;
+    use crate::BlobStore;
+
+    fn check_others(bs: &mut BlobStore) {
+        assert_eq!(bs.len(), 20);
+        assert_eq!(bs.iter().count(), 20);
+        for x in 1..20 {
+            assert_eq!(bs.get(&x).unwrap().get_v::<i32>().unwrap(), x);
+        }
+    }
+
+    #[test]
+    fn test_decode_torn() {
+        let rec = encode(500, &[(40, vec![1, 2, 3]), (0, vec![9; 40])]).unwrap();
+        let (flen, ranges) = decode(&mut &rec[..]).unwrap();
+        assert_eq!((flen, &ranges[0]), (500, &(40, vec![1, 2, 3])));
+        for n in 0..rec.len() {
+            assert!(decode(&mut &rec[..n]).is_none());
+        }
+        //a torn record after a whole one is left out
+        let mut two = rec.clone();
+        two.extend(encode(500, &[(7, vec![1])]).unwrap());
+        let mut r = &two[..two.len() - 1];
+        assert!(decode(&mut r).is_some());
+        assert!(decode(&mut r).is_none());
+    }
+
+    #[test]
+    fn test_crash_at_each_step() {
+        let fs = "test_data/wal_crash";
+        let mut crashes = 0;
+        for steps in 0..10 {
+            let mut bs = fresh(fs, true, |x| x);
+            bs.crash_after(steps);
+            let crashed = bs.insert(0, 500).is_err();
+            drop(bs);
+
+            let mut bs = BlobStore::open(fs).unwrap();
+            let v: i32 = bs.get(&0).unwrap().get_v().unwrap();
+            //a crash anywhere before the checkpoint rolls the whole insert back
+            assert_eq!(v, if crashed { 0 } else { 500 });
+            check_others(&mut bs);
+            if !crashed {
+                break;
+            }
+            crashes += 1;
+        }
+        //logged, mid insert, applied, synced
+        assert_eq!(crashes, 4);
+
+        //a crash while logging leaves a torn record, and the store as it was
+        let mut bs = fresh(fs, true, |x| x);
+        bs.crash_after(0);
+        assert!(bs.insert(0, 500).is_err());
+        drop(bs);
+        let wal = OpenOptions::new()
+            .write(true)
+            .open(format!("{}.wal", fs))
+            .unwrap();
+        let wlen = wal.metadata().unwrap().len();
+        assert!(wlen > 0);
+        wal.set_len(wlen - 3).unwrap();
+        let mut bs = BlobStore::open(fs).unwrap();
+        assert_eq!(bs.get(&0).unwrap().get_v::<i32>().unwrap(), 0);
+        check_others(&mut bs);
+
+        //without the log, dying between the remove and the write loses the key
+        let mut bs = fresh(fs, false, |x| x);
+        bs.crash_after(0);
+        assert!(bs.insert(0, 500).is_err());
+        drop(bs);
+        let bs = BlobStore::open(fs).unwrap();
+        assert!(bs.get(&0).is_err());
+    }
+
+    #[test]
+    fn test_failed_insert_rolls_back() {
+        let fs = "test_data/wal_no_room";
+        let mut bs = fresh(fs, true, |x| x);
+        let big = "x".repeat(340);
+        assert!(matches!(bs.insert(3, &big), Err(BlobError::NoRoom)));
+        assert_eq!(bs.get(&3).unwrap().get_v::<i32>().unwrap(), 3);
+        check_others(&mut bs);
+        bs.set_wal(false).unwrap();
+        assert!(!std::path::Path::new("test_data/wal_no_room.wal").exists());
+    }
+
+    #[test]
+    fn test_crash_with_overflow() {
+        let fs = "test_data/wal_overflow";
+        let (old, new) = ("o".repeat(1000), "n".repeat(1500));
+        for steps in 0..30 {
+            let mut bs = fresh(fs, true, |x| x);
+            bs.insert(0, &old).unwrap();
+            bs.crash_after(steps);
+            let crashed = bs.insert(0, &new).is_err();
+            drop(bs);
+
+            //the pages and the free chain roll back with the stub, so none are lost
+            let mut bs = BlobStore::open(fs).unwrap();
+            let v: String = bs.get(&0).unwrap().get_v().unwrap();
+            assert_eq!(&v, if crashed { &old } else { &new });
+            assert!(bs.verify().unwrap().is_ok());
+            bs.insert(1, "x".repeat(2000)).unwrap();
+            assert_eq!(bs.get(&0).unwrap().get_v::<String>().unwrap(), v);
+            if !crashed {
+                return;
+            }
+        }
+        panic!("never finished");
+    }
+}
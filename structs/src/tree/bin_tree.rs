@@ -152,7 +152,7 @@ pub mod test {
     #[test]
     pub fn exists_returns_false_when_tree_is_empty() {
         let tree = BinTree::new();
-        assert_eq!(tree.exists(4), false)
+        assert!(!tree.exists(4))
     }
 
     #[test]
@@ -165,7 +165,7 @@ pub mod test {
         tree.insert(7);
         tree.insert(9);
         tree.insert(17);
-        assert_eq!(tree.exists(4), false)
+        assert!(!tree.exists(4))
     }
 
     #[test]
@@ -178,12 +178,12 @@ pub mod test {
         tree.insert(7);
         tree.insert(9);
         tree.insert(17);
-        assert_eq!(tree.exists(5), true);
-        assert_eq!(tree.exists(2), true);
-        assert_eq!(tree.exists(6), true);
-        assert_eq!(tree.exists(3), true);
-        assert_eq!(tree.exists(7), true);
-        assert_eq!(tree.exists(9), true);
-        assert_eq!(tree.exists(17), true);
+        assert!(tree.exists(5));
+        assert!(tree.exists(2));
+        assert!(tree.exists(6));
+        assert!(tree.exists(3));
+        assert!(tree.exists(7));
+        assert!(tree.exists(9));
+        assert!(tree.exists(17));
     }
 }
@@ -3,6 +3,10 @@ use std::{collections::BTreeMap, fmt::Display};
 
 use thiserror::Error;
 
+///The deepest tree from_bytes reads, so untrusted bytes can't recurse it off the stack.
+///A tree built from a string only gets d deep with about the d'th Fibonacci number of chars.
+const MAX_DEPTH: usize = 256;
+
 #[derive(Error, Debug)]
 enum HuffManError {
     #[error("Attempt to traverse beyond Hufffman Tree leaf")]
@@ -89,11 +93,45 @@ impl HuffNode {
         let mut res = Vec::new();
         for c in s.chars() {
             let v = self.encode_char(c)?;
-            res.extend(v.into_iter());
+            res.extend(v);
         }
         Some(res)
     }
 
+    ///Writes the tree depth first, 0 for a branch and 1 then the char for a leaf
+    fn out(&self, res: &mut Vec<u8>) {
+        match self {
+            HuffNode::Tree(l, r) => {
+                res.push(0);
+                l.out(res);
+                r.out(res);
+            }
+            HuffNode::Leaf(c) => {
+                res.push(1);
+                res.extend((*c as u32).to_le_bytes());
+            }
+        }
+    }
+
+    ///Reads back what out wrote, giving up on a tree deeper than MAX_DEPTH
+    fn read(b: &mut &[u8], depth: usize) -> Option<Self> {
+        let (&tag, rest) = b.split_first()?;
+        *b = rest;
+        match tag {
+            0 if depth < MAX_DEPTH => {
+                let l = Self::read(b, depth + 1)?;
+                let r = Self::read(b, depth + 1)?;
+                Some(HuffNode::Tree(Box::new(l), Box::new(r)))
+            }
+            1 => {
+                let (c, rest) = b.split_first_chunk::<4>()?;
+                *b = rest;
+                Some(HuffNode::Leaf(char::from_u32(u32::from_le_bytes(*c))?))
+            }
+            _ => None,
+        }
+    }
+
     pub fn left(&self) -> Result<&Self, HuffManError> {
         self.traverse(HuffmanDir::Left)
     }
@@ -161,6 +199,34 @@ impl HuffEncodedString {
         }
     }
 
+    ///The tree then the encoded bits, for from_bytes to rebuild it.
+    ///None if there are no bits, as when every char is the same.
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        let cbv = self.encoded_string.as_ref()?;
+        let mut res = Vec::new();
+        self.tree.out(&mut res);
+        res.push(cbv.bits_in_last_byte);
+        res.extend(&cbv.compressed_vec);
+        Some(res)
+    }
+
+    pub fn from_bytes(mut b: &[u8]) -> Option<Self> {
+        let tree = HuffNode::read(&mut b, 0)?;
+        let (&bits_in_last_byte, compressed_vec) = b.split_first()?;
+        //a lone leaf has no bits to walk it by
+        let leaf = matches!(tree, HuffNode::Leaf(_));
+        if leaf || compressed_vec.is_empty() || bits_in_last_byte > 8 {
+            return None;
+        }
+        Some(Self {
+            tree,
+            encoded_string: Some(CompressedBoolVec {
+                bits_in_last_byte,
+                compressed_vec: compressed_vec.to_vec(),
+            }),
+        })
+    }
+
     fn string_as_digits(&self) -> Option<String> {
         self.encoded_string.as_ref().map(|cbv| {
             cbv.decompress()
@@ -293,6 +359,18 @@ impl CompressedBoolVec {
 pub mod test {
     use super::*;
 
+    #[test]
+    fn test_bytes_round_trip() {
+        let s = "so long and thanks for all the fish \u{e9}\u{1f41f}";
+        let b = HuffEncodedString::encode(s).to_bytes().unwrap();
+        let back = HuffEncodedString::from_bytes(&b).unwrap();
+        assert_eq!(back.decode().unwrap(), s);
+        assert!(HuffEncodedString::from_bytes(&b[..b.len() / 2]).is_none());
+        assert!(HuffEncodedString::encode("aaaa").to_bytes().is_none());
+        //nested past any real tree, and far past the stack
+        assert!(HuffEncodedString::from_bytes(&vec![0; 1 << 21]).is_none());
+    }
+
     #[test]
     fn print_htree() {
         let s = r#"Lorem Ipsum